@@ -0,0 +1,120 @@
+//! Measures how quickly colx processes large inputs: `cargo bench` runs the release binary on
+//! generated input for several common invocations and reports the throughput of each.  The amount
+//! of input defaults to 256 MiB and can be changed with COLX_BENCH_MB, e.g.
+//! `COLX_BENCH_MB=4096 cargo bench` for multi-GB inputs.  The input is generated in memory and
+//! piped to colx, so no disk space is needed and disk speed doesn't affect the results.
+
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Instant;
+
+const DEFAULT_MEGABYTES: usize = 256;
+const MEBIBYTE: usize = 1024 * 1024;
+
+/// A colx invocation to measure.
+struct Scenario {
+    name: &'static str,
+    args: &'static [&'static str],
+    csv: bool,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "regex delimiter",
+        args: &["1", "3", "5"],
+        csv: false,
+    },
+    Scenario {
+        name: "fixed delimiter",
+        args: &["-F", "-d", " ", "1", "3", "5"],
+        csv: false,
+    },
+    Scenario {
+        name: "column range",
+        args: &["-F", "-d", " ", "2:6"],
+        csv: false,
+    },
+    Scenario {
+        name: "excluded columns",
+        args: &["-F", "-d", " ", "^2:3"],
+        csv: false,
+    },
+    Scenario {
+        name: "csv",
+        args: &["--csv", "1", "3"],
+        csv: true,
+    },
+];
+
+/// Returns about a mebibyte of lines with eight columns of varying widths, separated by spaces or
+/// commas.  Every other CSV line has a quoted field containing a comma.
+fn generate_chunk(csv: bool) -> Vec<u8> {
+    let mut chunk = vec![];
+    let mut i: usize = 0;
+    while chunk.len() < MEBIBYTE {
+        let fields = [
+            format!("host{}", i % 97),
+            format!("{}", i * 7919 % 100_000),
+            String::from("GET"),
+            format!("/path/to/resource/{}", i % 1013),
+            format!("{}", 200 + i % 5),
+            format!("{}.{:03}", i % 60, i % 1000),
+            if csv && i.is_multiple_of(2) {
+                String::from("\"Mozilla/5.0, like Gecko\"")
+            } else {
+                String::from("Mozilla/5.0")
+            },
+            format!("request-{i}"),
+        ];
+        chunk.extend_from_slice(fields.join(if csv { "," } else { " " }).as_bytes());
+        chunk.push(b'\n');
+        i += 1;
+    }
+    chunk
+}
+
+/// Runs colx with args, piping chunk to it repeatedly until total_bytes have been written, and
+/// returns the elapsed time in seconds.
+fn run(args: &[&str], chunk: &[u8], total_bytes: usize) -> f64 {
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_colx"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to run colx");
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let mut written = 0;
+    while written < total_bytes {
+        stdin.write_all(chunk).expect("failed writing to colx");
+        written += chunk.len();
+    }
+    drop(stdin);
+    let status = child.wait().expect("failed waiting for colx");
+    assert!(status.success(), "colx {args:?} failed: {status}");
+    start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let megabytes = match std::env::var("COLX_BENCH_MB") {
+        Ok(value) => value
+            .parse()
+            .expect("COLX_BENCH_MB must be a number of mebibytes"),
+        Err(_) => DEFAULT_MEGABYTES,
+    };
+    let total_bytes = megabytes * MEBIBYTE;
+    for csv in [false, true] {
+        let chunk = generate_chunk(csv);
+        for scenario in SCENARIOS.iter().filter(|scenario| scenario.csv == csv) {
+            let seconds = run(scenario.args, &chunk, total_bytes);
+            println!(
+                "{:<20} {:>8} MiB in {:>7.2} s: {:>8.1} MiB/s",
+                scenario.name,
+                megabytes,
+                seconds,
+                megabytes as f64 / seconds
+            );
+        }
+    }
+}
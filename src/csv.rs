@@ -0,0 +1,255 @@
+//! Support for reading RFC 4180 CSV input.
+//!
+//! Fields may be quoted with double quotes, in which case they can contain commas, newlines, and
+//! double quotes (escaped by doubling them).  Parsing is lenient: a double quote that doesn't start
+//! a field is kept as a literal character, as is anything following the closing quote of a quoted
+//! field, and an unterminated quoted field runs to the end of the input.
+
+use std::io::Result;
+use std::ops::Range;
+
+/// Splits a CSV record into fields, removing quotes and unescaping doubled quotes, and appending
+/// the fields to text and their positions in text to spans, so that records can be parsed into
+/// buffers that are reused for every record.  An empty record has no fields.
+pub fn parse_record_into(record: &[u8], text: &mut Vec<u8>, spans: &mut Vec<Range<usize>>) {
+    if record.is_empty() {
+        return;
+    }
+
+    let mut start = text.len();
+    let mut in_quotes = false;
+    let mut field_start = true;
+    let mut bytes = record.iter().peekable();
+    while let Some(&b) = bytes.next() {
+        if in_quotes {
+            if b != b'"' {
+                text.push(b);
+            } else if bytes.peek() == Some(&&b'"') {
+                text.push(b'"');
+                bytes.next();
+            } else {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match b {
+            b',' => {
+                spans.push(start..text.len());
+                start = text.len();
+                field_start = true;
+                continue;
+            }
+            b'"' if field_start => in_quotes = true,
+            _ => text.push(b),
+        }
+        field_start = false;
+    }
+    spans.push(start..text.len());
+}
+
+/// Whether the lines of a record read so far end inside a quoted field, i.e. whether the record
+/// continues on the next line.  Quotes are tracked the same way as parse_record_into() does, one
+/// line at a time, so that each line is only scanned once.
+struct QuoteState {
+    in_quotes: bool,
+    field_start: bool,
+}
+
+impl QuoteState {
+    fn new() -> Self {
+        Self {
+            in_quotes: false,
+            field_start: true,
+        }
+    }
+
+    /// Updates the state for the next line of the record.  A line only follows one that ends inside
+    /// quotes, and a quote at the end of a line can't be doubled, because the line is followed by a
+    /// newline if the record continues.
+    fn scan_line(&mut self, line: &[u8]) {
+        let mut bytes = line.iter().peekable();
+        while let Some(&b) = bytes.next() {
+            if self.in_quotes {
+                if b == b'"' && bytes.next_if_eq(&&b'"').is_none() {
+                    self.in_quotes = false;
+                }
+                continue;
+            }
+            match b {
+                b',' => self.field_start = true,
+                b'"' if self.field_start => {
+                    self.in_quotes = true;
+                    self.field_start = false;
+                }
+                _ => self.field_start = false,
+            }
+        }
+    }
+}
+
+/// An iterator that joins lines into CSV records, so that quoted fields can contain newlines.
+/// Lines must keep the "\r" of "\r\n" line endings, and are joined with "\n", so that line breaks
+/// in quoted fields are unchanged.  The line ending is removed from the end of each record.
+pub struct CsvRecords<I> {
+    lines: I,
+}
+
+impl<I: Iterator<Item = Result<Vec<u8>>>> CsvRecords<I> {
+    /// Wraps an iterator of lines, e.g. from [std::io::BufRead::split].
+    pub fn new(lines: I) -> Self {
+        Self { lines }
+    }
+}
+
+impl<I: Iterator<Item = Result<Vec<u8>>>> Iterator for CsvRecords<I> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut quotes = QuoteState::new();
+        quotes.scan_line(&record);
+        while quotes.in_quotes {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    quotes.scan_line(&line);
+                    record.push(b'\n');
+                    record.extend_from_slice(&line);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                // Unterminated quoted field at the end of the input.
+                None => break,
+            }
+        }
+        if record.last() == Some(&b'\r') {
+            record.pop();
+        }
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod parse_record {
+    use super::*;
+
+    fn parse_record(record: &[u8]) -> Vec<Vec<u8>> {
+        // Fields are appended, so their positions are after anything already in text.
+        let mut text = b"ignored".to_vec();
+        let mut spans = vec![];
+        parse_record_into(record, &mut text, &mut spans);
+        spans.into_iter().map(|span| text[span].to_vec()).collect()
+    }
+
+    fn parse(record: &str) -> Vec<String> {
+        parse_record(record.as_bytes())
+            .into_iter()
+            .map(|field| String::from_utf8(field).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn simple() {
+        assert_eq!(vec!["a", "b", "c"], parse("a,b,c"));
+    }
+
+    #[test]
+    fn empty_record() {
+        assert_eq!(Vec::<String>::new(), parse(""));
+    }
+
+    #[test]
+    fn empty_fields() {
+        assert_eq!(vec!["", "b", "", ""], parse(",b,,"));
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(
+            vec!["a,b", "say \"hi\"", "", "line\nbreak"],
+            parse("\"a,b\",\"say \"\"hi\"\"\",\"\",\"line\nbreak\"")
+        );
+    }
+
+    #[test]
+    fn lenient_quotes() {
+        assert_eq!(vec!["a\"b", "cd\"e"], parse("a\"b,\"cd\"\"\"e"));
+        assert_eq!(vec!["unterminated,field"], parse("\"unterminated,field"));
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(vec!["héllo", "wörld"], parse("héllo,\"wörld\""));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            vec![b"\xff".to_vec(), b"a\xfe,".to_vec()],
+            parse_record(b"\xff,\"a\xfe,\"")
+        );
+    }
+}
+
+#[cfg(test)]
+mod csv_records {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<String> {
+        CsvRecords::new(lines.iter().map(|line| Ok(line.as_bytes().to_vec())))
+            .map(|record| String::from_utf8(record.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn one_line_records() {
+        assert_eq!(vec!["a,b", "c,d"], records(&["a,b", "c,d"]));
+    }
+
+    #[test]
+    fn multi_line_record() {
+        assert_eq!(
+            vec!["a,\"b\n\nc\",d", "e"],
+            records(&["a,\"b", "", "c\",d", "e"])
+        );
+    }
+
+    #[test]
+    fn crlf_line_breaks() {
+        assert_eq!(
+            vec!["a,\"b\r\nc\",d", "e"],
+            records(&["a,\"b\r", "c\",d\r", "e\r"])
+        );
+    }
+
+    #[test]
+    fn quotes_across_lines() {
+        assert_eq!(vec!["a,\"b\"", "c"], records(&["a,\"b\"", "c"]));
+        assert_eq!(vec!["a\"b", "c"], records(&["a\"b", "c"]));
+        assert_eq!(vec!["\"a\"\"\nb\"", "c"], records(&["\"a\"\"", "b\"", "c"]));
+        assert_eq!(vec!["\"a\n\"b,c", "d"], records(&["\"a", "\"b,c", "d"]));
+        assert_eq!(vec!["\"a\n\"b,\"c\nd"], records(&["\"a", "\"b,\"c", "d"]));
+        assert_eq!(
+            vec!["\"a\n\"\"b\nc\"", "d"],
+            records(&["\"a", "\"\"b", "c\"", "d"])
+        );
+    }
+
+    #[test]
+    fn unterminated_record() {
+        assert_eq!(vec!["a,\"b\nc"], records(&["a,\"b", "c"]));
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let lines = vec![Ok(b"\"a".to_vec()), Err(std::io::Error::other("oh no!"))];
+        let mut csv_records = CsvRecords::new(lines.into_iter());
+        assert!(csv_records.next().unwrap().is_err());
+
+        let lines: Vec<Result<Vec<u8>>> = vec![Err(std::io::Error::other("oh no!"))];
+        let mut csv_records = CsvRecords::new(lines.into_iter());
+        assert!(csv_records.next().unwrap().is_err());
+        assert!(csv_records.next().is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Transparent decompression of compressed inputs.
+//!
+//! Compressed inputs are detected by the magic bytes at the start of the input rather than by the
+//! filename, so compressed data on stdin and rotated logs with unhelpful names are handled too.
+//! gzip, zstd, xz, and bzip2 are supported; anything else is passed through unchanged.
+//! Concatenated streams (e.g. from `cat a.gz b.gz`) are decompressed in full.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Result;
+
+use ruzstd::decoding::FrameDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+/// The compression formats that are recognised.
+#[derive(Debug, PartialEq)]
+enum Format {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// Magic bytes for each format, from the respective file format specifications.  bzip2 is checked
+/// by is_bzip2() instead, because its magic bytes alone are printable text.
+const MAGIC: [(Format, &[u8]); 3] = [
+    (Format::Gzip, &[0x1f, 0x8b]),
+    (Format::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+    (Format::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+];
+
+/// The magic number that starts a compressed block in a bzip2 stream.
+const BZIP2_BLOCK_MAGIC: &[u8] = &[0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+
+/// The magic number that ends a bzip2 stream, which follows the header of an empty stream.
+const BZIP2_END_MAGIC: &[u8] = &[0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+/// The number of bytes needed to recognise any of the formats.
+const MAGIC_LENGTH: usize = 10;
+
+/// Returns the compression format of data starting with prefix, or None if it isn't compressed.
+fn detect(prefix: &[u8]) -> Option<Format> {
+    if is_bzip2(prefix) {
+        return Some(Format::Bzip2);
+    }
+    MAGIC
+        .into_iter()
+        .find(|(_, magic)| prefix.starts_with(magic))
+        .map(|(format, _)| format)
+}
+
+/// Returns whether prefix starts a bzip2 stream: "BZh", a block size from 1 to 9, and then the
+/// magic number of a block or of the end of the stream.  Checking more than "BZh" means that text
+/// starting with e.g. "BZh is" isn't mistaken for bzip2.
+fn is_bzip2(prefix: &[u8]) -> bool {
+    match prefix {
+        [b'B', b'Z', b'h', b'1'..=b'9', rest @ ..] => {
+            rest.starts_with(BZIP2_BLOCK_MAGIC) || rest.starts_with(BZIP2_END_MAGIC)
+        }
+        _ => false,
+    }
+}
+
+/// Reads up to MAGIC_LENGTH bytes from reader, stopping early only at EOF.  A single read() isn't
+/// guaranteed to return enough bytes, particularly from pipes.
+fn read_prefix(reader: &mut dyn Read) -> Result<Vec<u8>> {
+    let mut prefix = vec![0; MAGIC_LENGTH];
+    let mut length = 0;
+    while length < MAGIC_LENGTH {
+        match reader.read(&mut prefix[length..]) {
+            Ok(0) => break,
+            Ok(n) => length += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    prefix.truncate(length);
+    Ok(prefix)
+}
+
+/// Decodes zstd input, continuing with the next frame when a frame ends.  ruzstd's
+/// StreamingDecoder stops after the first frame.
+struct ZstdDecoder<R: Read> {
+    // Only None while moving to the next frame.
+    decoder: Option<StreamingDecoder<BufReader<R>, FrameDecoder>>,
+}
+
+impl<R: Read> ZstdDecoder<R> {
+    fn new(source: R) -> Result<Self> {
+        let decoder =
+            StreamingDecoder::new(BufReader::new(source)).map_err(std::io::Error::other)?;
+        Ok(Self {
+            decoder: Some(decoder),
+        })
+    }
+}
+
+impl<R: Read> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let decoder = self.decoder.as_mut().expect("zstd decoder missing");
+            let length = decoder.read(buf)?;
+            if length > 0 || buf.is_empty() || decoder.get_mut().fill_buf()?.is_empty() {
+                return Ok(length);
+            }
+            // The frame has ended but there is more input, so start decoding the next frame.
+            let (source, frame_decoder) = self
+                .decoder
+                .take()
+                .expect("zstd decoder missing")
+                .into_parts();
+            self.decoder = Some(
+                StreamingDecoder::new_with_decoder(source, frame_decoder)
+                    .map_err(std::io::Error::other)?,
+            );
+        }
+    }
+}
+
+/// Wraps a filehandle, decompressing its contents if they're compressed.  Detection is deferred
+/// until the first read() so that creating a Decompressor never blocks, e.g. on stdin.
+pub struct Decompressor {
+    inner: Box<dyn Read>,
+    detected: bool,
+}
+
+impl Decompressor {
+    pub fn new(inner: Box<dyn Read>) -> Self {
+        Self {
+            inner,
+            detected: false,
+        }
+    }
+
+    /// Replaces inner with a reader that returns the decompressed data.  The bytes consumed while
+    /// detecting the format are replayed ahead of the rest of the input.
+    fn detect(&mut self) -> Result<()> {
+        let prefix = read_prefix(&mut self.inner)?;
+        let format = detect(&prefix);
+        let inner = std::mem::replace(&mut self.inner, Box::new(std::io::empty()));
+        let input = Cursor::new(prefix).chain(inner);
+        self.inner = match format {
+            None => Box::new(input),
+            Some(Format::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Some(Format::Zstd) => Box::new(ZstdDecoder::new(input)?),
+            Some(Format::Xz) => Box::new(lzma_rust2::XzReader::new(input, true)),
+            Some(Format::Bzip2) => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+        };
+        self.detected = true;
+        Ok(())
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.detected {
+            self.detect()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod detect {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(Some(Format::Gzip), detect(&[0x1f, 0x8b, 0x08, 0x00]));
+        assert_eq!(Some(Format::Zstd), detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x24]));
+        assert_eq!(Some(Format::Xz), detect(b"\xfd7zXZ\x00"));
+        assert_eq!(Some(Format::Bzip2), detect(b"BZh91AY&SY"));
+        assert_eq!(Some(Format::Bzip2), detect(b"BZh1\x17\x72\x45\x38\x50\x90"));
+    }
+
+    #[test]
+    fn not_compressed() {
+        assert_eq!(None, detect(b""));
+        assert_eq!(None, detect(b"hello world"));
+        assert_eq!(None, detect(&[0x1f]));
+        assert_eq!(None, detect(b"\xfd7zXZ"));
+        assert_eq!(None, detect(b"BZ"));
+        assert_eq!(None, detect(b"BZh is a prefix"));
+        assert_eq!(None, detect(b"BZh9 blocks"));
+        assert_eq!(None, detect(b"BZh01AY&SY"));
+    }
+}
+
+#[cfg(test)]
+mod decompressor {
+    use super::*;
+    use std::fs::File;
+
+    /// An implementation of [std::io::Read] that returns one byte per read(), like a slow pipe.
+    struct OneByteAtATime {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl Read for OneByteAtATime {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.position >= self.data.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.data[self.position];
+            self.position += 1;
+            Ok(1)
+        }
+    }
+
+    fn read_all(reader: Box<dyn Read>) -> String {
+        let mut output = String::new();
+        Decompressor::new(reader)
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    fn read_file(filename: &str) -> String {
+        read_all(Box::new(File::open(filename).unwrap()))
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(
+            "This is file 1.\n\nIt is not very interesting.\n",
+            read_file("testdata/file1")
+        );
+    }
+
+    #[test]
+    fn compressed() {
+        let expected = read_file("testdata/file1");
+        for filename in [
+            "testdata/file1.gz",
+            "testdata/file1.zst",
+            "testdata/file1.xz",
+            "testdata/file1.bz2",
+        ] {
+            assert_eq!(expected, read_file(filename), "{}", filename);
+        }
+    }
+
+    #[test]
+    fn concatenated() {
+        let expected = read_file("testdata/file1") + &read_file("testdata/file2");
+        for filename in [
+            "testdata/file1_and_file2.gz",
+            "testdata/file1_and_file2.zst",
+            "testdata/file1_and_file2.xz",
+            "testdata/file1_and_file2.bz2",
+        ] {
+            assert_eq!(expected, read_file(filename), "{}", filename);
+        }
+    }
+
+    #[test]
+    fn short_reads() {
+        let data = std::fs::read("testdata/file1.gz").unwrap();
+        let reader = OneByteAtATime { data, position: 0 };
+        assert_eq!(read_file("testdata/file1"), read_all(Box::new(reader)));
+    }
+
+    #[test]
+    fn shorter_than_magic() {
+        assert_eq!("a", read_all(Box::new(Cursor::new(b"a".to_vec()))));
+        assert_eq!("", read_all(Box::new(std::io::empty())));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut data = vec![0x1f, 0x8b];
+        data.extend_from_slice(b"this is not gzip data");
+        let mut output = vec![];
+        assert!(
+            Decompressor::new(Box::new(Cursor::new(data)))
+                .read_to_end(&mut output)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Splitting lines into columns at a delimiter, which is either a regex or a fixed string.
+//!
+//! Fixed string delimiters are found with memchr (for a single byte) or memmem (for longer
+//! strings), which is much faster than the regex engine on large inputs.
+
+use std::ops::Range;
+
+use memchr::memmem;
+use regex::bytes::Regex;
+
+/// A compiled delimiter.
+pub enum Delimiter {
+    Regex(Regex),
+    Byte(u8),
+    Bytes(Box<memmem::Finder<'static>>),
+}
+
+impl Delimiter {
+    /// Compiles a regex delimiter.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Delimiter::Regex)
+    }
+
+    /// Returns a delimiter matching the fixed string delimiter, which must not be empty.
+    pub fn fixed(delimiter: &str) -> Result<Self, String> {
+        match delimiter.as_bytes() {
+            [] => Err(String::from("Fixed delimiter must not be empty.")),
+            [byte] => Ok(Delimiter::Byte(*byte)),
+            bytes => Ok(Delimiter::Bytes(Box::new(
+                memmem::Finder::new(bytes).into_owned(),
+            ))),
+        }
+    }
+
+    /// Returns an iterator over the positions in line of the parts separated by the delimiter, like
+    /// [Regex::split] but returning ranges rather than slices.  Leading and trailing empty parts are
+    /// kept, and an empty line has one empty part.
+    pub fn split_ranges<'d, 'l>(&'d self, line: &'l [u8]) -> Split<'d, 'l> {
+        let spans = match self {
+            Delimiter::Regex(regex) => Spans::Regex(regex.find_iter(line)),
+            Delimiter::Byte(byte) => Spans::Byte(memchr::memchr_iter(*byte, line)),
+            Delimiter::Bytes(finder) => Spans::Bytes(finder, line, 0),
+        };
+        Split {
+            length: line.len(),
+            spans,
+            last: 0,
+            finished: false,
+        }
+    }
+
+    /// Like [Delimiter::split_ranges], but returns the parts of line.
+    #[cfg(test)]
+    pub fn split<'l>(&self, line: &'l [u8]) -> impl Iterator<Item = &'l [u8]> {
+        self.split_ranges(line).map(|range| &line[range])
+    }
+}
+
+/// The positions of the delimiters in a line.
+enum Spans<'d, 'l> {
+    Regex(regex::bytes::Matches<'d, 'l>),
+    Byte(memchr::Memchr<'l>),
+    // memmem::FindIter is much larger than the other iterators, so search from an offset instead.
+    Bytes(&'d memmem::Finder<'static>, &'l [u8], usize),
+}
+
+impl Iterator for Spans<'_, '_> {
+    /// The start and end of a delimiter.
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Spans::Regex(matches) => matches.next().map(|m| (m.start(), m.end())),
+            Spans::Byte(positions) => positions.next().map(|start| (start, start + 1)),
+            Spans::Bytes(finder, line, offset) => {
+                let start = *offset + finder.find(&line[*offset..])?;
+                *offset = start + finder.needle().len();
+                Some((start, *offset))
+            }
+        }
+    }
+}
+
+/// An iterator over the positions of the parts of a line separated by a delimiter, returned by
+/// [Delimiter::split_ranges].
+pub struct Split<'d, 'l> {
+    length: usize,
+    spans: Spans<'d, 'l>,
+    last: usize,
+    finished: bool,
+}
+
+impl Iterator for Split<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.spans.next() {
+            Some((start, end)) => {
+                let part = self.last..start;
+                self.last = end;
+                Some(part)
+            }
+            None => {
+                self.finished = true;
+                Some(self.last..self.length)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod split {
+    use super::*;
+
+    fn split(delimiter: &Delimiter, line: &str) -> Vec<String> {
+        delimiter
+            .split(line.as_bytes())
+            .map(|part| String::from_utf8(part.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn regex() {
+        let delimiter = Delimiter::regex(r"\s+").unwrap();
+        assert_eq!(vec!["", "a", "b", ""], split(&delimiter, " a \t b "));
+        assert_eq!(vec![""], split(&delimiter, ""));
+    }
+
+    #[test]
+    fn regex_matches_regex_split() {
+        let regex = Regex::new(r"[,;]+").unwrap();
+        let delimiter = Delimiter::regex(r"[,;]+").unwrap();
+        for line in ["", "a", ",a,,b;", "a,b;;c", ";"] {
+            let expected: Vec<&[u8]> = regex.split(line.as_bytes()).collect();
+            let actual: Vec<&[u8]> = delimiter.split(line.as_bytes()).collect();
+            assert_eq!(expected, actual, "{}", line);
+        }
+    }
+
+    #[test]
+    fn fixed_byte() {
+        let delimiter = Delimiter::fixed("|").unwrap();
+        assert_eq!(vec!["a", "", "b", ""], split(&delimiter, "a||b|"));
+        // Regex metacharacters are literal.
+        let delimiter = Delimiter::fixed(".").unwrap();
+        assert_eq!(vec!["a", "b"], split(&delimiter, "a.b"));
+    }
+
+    #[test]
+    fn fixed_bytes() {
+        let delimiter = Delimiter::fixed("::").unwrap();
+        assert_eq!(vec!["", "a", "b:c", ""], split(&delimiter, "::a::b:c::"));
+        // Matches don't overlap.
+        assert_eq!(vec!["a", ":b"], split(&delimiter, "a:::b"));
+        assert_eq!(vec!["abc"], split(&delimiter, "abc"));
+    }
+
+    #[test]
+    fn fixed_empty() {
+        assert!(Delimiter::fixed("").is_err());
+    }
+}
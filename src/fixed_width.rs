@@ -0,0 +1,301 @@
+//! Splitting fixed-width records into columns at fixed positions, for --widths, --cut-points, and
+//! --aligned.
+//!
+//! Positions count bytes by default, or characters with --characters.  Characters are counted by
+//! skipping UTF-8 continuation bytes, so invalid UTF-8 never causes an error.
+
+use std::ops::Range;
+
+/// Splits lines into columns at fixed positions.
+pub struct FixedWidth {
+    /// The number of bytes or characters before each cut, in increasing order.
+    cut_points: Vec<usize>,
+    characters: bool,
+    trim: bool,
+}
+
+impl FixedWidth {
+    /// Returns a FixedWidth whose columns have the given widths.
+    pub fn from_widths(widths: &[usize], characters: bool, trim: bool) -> Self {
+        let cut_points = widths
+            .iter()
+            .scan(0, |position, width| {
+                *position += width;
+                Some(*position)
+            })
+            .collect();
+        Self {
+            cut_points,
+            characters,
+            trim,
+        }
+    }
+
+    /// Returns a FixedWidth that cuts lines after each of cut_points, which must be increasing.
+    pub fn from_cut_points(
+        cut_points: &[usize],
+        characters: bool,
+        trim: bool,
+    ) -> Result<Self, String> {
+        if cut_points.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(String::from("--cut-points must be in increasing order."));
+        }
+        Ok(Self {
+            cut_points: cut_points.to_vec(),
+            characters,
+            trim,
+        })
+    }
+
+    /// Returns a FixedWidth for a table whose columns are aligned with the words in its header, like
+    /// the output of ps or kubectl get.  lines[0] is the header, and any other lines are samples
+    /// of the table's contents.  Each word in the header after the first starts a column, but the
+    /// cut is moved left into the gap before the word if a sample line has a value that extends
+    /// further left, e.g. a right aligned number, and the columns are merged if no position in the
+    /// gap is blank in every sample line, e.g. because the header contains "CONTAINER ID".  Columns
+    /// are always trimmed.
+    pub fn from_aligned(lines: &[&[u8]], characters: bool) -> Self {
+        let blanks: Vec<Vec<bool>> = lines
+            .iter()
+            .map(|line| blank_positions(line, characters))
+            .collect();
+        // Positions past the end of a line are blank.
+        let is_blank = |position: usize| {
+            blanks
+                .iter()
+                .all(|line| line.get(position).copied().unwrap_or(true))
+        };
+        let header = blanks.first().map_or(&[][..], Vec::as_slice);
+        let mut cut_points = vec![];
+        let mut gap_start = None;
+        let mut seen_word = false;
+        for (position, &blank) in header.iter().enumerate() {
+            if blank {
+                gap_start = gap_start.or(Some(position));
+                continue;
+            }
+            // The first word always starts the first column.
+            if let Some(gap_start) = gap_start.filter(|_| seen_word)
+                && let Some(cut_point) = (gap_start + 1..=position)
+                    .rev()
+                    .find(|&cut_point| is_blank(cut_point - 1))
+            {
+                cut_points.push(cut_point);
+            }
+            gap_start = None;
+            seen_word = true;
+        }
+        Self {
+            cut_points,
+            characters,
+            trim: true,
+        }
+    }
+
+    /// Returns the byte offset of each cut point in line, clamped to the length of the line.  The
+    /// offsets are generated rather than collected so that splitting lines doesn't allocate.
+    fn offsets<'a>(&'a self, line: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let mut char_starts = line
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| *byte & 0xc0 != 0x80)
+            .map(|(i, _)| i);
+        // Cut points never decrease, so each one continues from the character after the last.
+        let mut next_char = 0;
+        let mut offset = 0;
+        self.cut_points.iter().map(move |&cut_point| {
+            if !self.characters {
+                return cut_point.min(line.len());
+            }
+            if cut_point >= next_char {
+                offset = char_starts.nth(cut_point - next_char).unwrap_or(line.len());
+                next_char = cut_point + 1;
+            }
+            offset
+        })
+    }
+
+    /// Splits line into columns, appending the position of the whole line followed by the
+    /// positions of the columns to ranges.  There is one column per cut point, which is empty if
+    /// the line is too short, followed by the rest of the line if it isn't empty.  Spaces and tabs
+    /// around each column are removed if trim is set.  An empty line has no columns.
+    pub fn split_ranges(&self, line: &[u8], ranges: &mut Vec<Range<usize>>) {
+        ranges.push(0..line.len());
+        if line.is_empty() {
+            return;
+        }
+        let mut start = 0;
+        for end in self.offsets(line) {
+            ranges.push(self.column_range(line, start..end));
+            start = end;
+        }
+        if start < line.len() {
+            ranges.push(self.column_range(line, start..line.len()));
+        }
+    }
+
+    /// Like [FixedWidth::split_ranges], but returns the whole line followed by the columns.
+    #[cfg(test)]
+    pub fn split<'a>(&self, line: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut ranges = vec![];
+        self.split_ranges(line, &mut ranges);
+        ranges.into_iter().map(|range| &line[range]).collect()
+    }
+
+    /// Returns range, without the spaces and tabs at either end of it in line if trim is set.
+    fn column_range(&self, line: &[u8], range: Range<usize>) -> Range<usize> {
+        if self.trim {
+            trim_padding(line, range)
+        } else {
+            range
+        }
+    }
+}
+
+/// Returns whether each byte or character in line is a space or tab.
+fn blank_positions(line: &[u8], characters: bool) -> Vec<bool> {
+    line.iter()
+        .filter(|byte| !characters || *byte & 0xc0 != 0x80)
+        .map(|byte| *byte == b' ' || *byte == b'\t')
+        .collect()
+}
+
+/// Removes leading and trailing spaces and tabs from the part of line in range.
+fn trim_padding(line: &[u8], range: Range<usize>) -> Range<usize> {
+    let is_padding = |byte: &u8| *byte == b' ' || *byte == b'\t';
+    let column = &line[range.clone()];
+    let start = column
+        .iter()
+        .position(|byte| !is_padding(byte))
+        .unwrap_or(column.len());
+    let end = column
+        .iter()
+        .rposition(|byte| !is_padding(byte))
+        .map_or(start, |i| i + 1);
+    range.start + start..range.start + end
+}
+
+#[cfg(test)]
+mod split {
+    use super::*;
+
+    fn split(fixed_width: &FixedWidth, line: &str) -> Vec<String> {
+        fixed_width
+            .split(line.as_bytes())
+            .into_iter()
+            .map(|column| String::from_utf8(column.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn widths() {
+        let fixed_width = FixedWidth::from_widths(&[3, 2], false, false);
+        assert_eq!(
+            vec!["abcdef", "abc", "de", "f"],
+            split(&fixed_width, "abcdef")
+        );
+        assert_eq!(vec!["abcde", "abc", "de"], split(&fixed_width, "abcde"));
+    }
+
+    #[test]
+    fn short_lines_have_empty_columns() {
+        let fixed_width = FixedWidth::from_widths(&[3, 2, 4], false, false);
+        assert_eq!(vec!["ab", "ab", "", ""], split(&fixed_width, "ab"));
+    }
+
+    #[test]
+    fn empty_line() {
+        let fixed_width = FixedWidth::from_widths(&[3], false, false);
+        assert_eq!(vec![""], split(&fixed_width, ""));
+    }
+
+    #[test]
+    fn cut_points() {
+        let fixed_width = FixedWidth::from_cut_points(&[2, 5], false, false).unwrap();
+        assert_eq!(
+            vec!["abcdefg", "ab", "cde", "fg"],
+            split(&fixed_width, "abcdefg")
+        );
+        assert!(FixedWidth::from_cut_points(&[5, 2], false, false).is_err());
+        assert!(FixedWidth::from_cut_points(&[2, 2], false, false).is_err());
+    }
+
+    #[test]
+    fn trim() {
+        let fixed_width = FixedWidth::from_widths(&[5, 5], false, true);
+        assert_eq!(
+            vec!["a b  \t c  d", "a b", "c", "d"],
+            split(&fixed_width, "a b  \t c  d")
+        );
+        assert_eq!(vec!["     ", "", ""], split(&fixed_width, "     "));
+    }
+
+    #[test]
+    fn characters() {
+        let fixed_width = FixedWidth::from_widths(&[2, 1], true, false);
+        assert_eq!(
+            vec!["日本語x", "日本", "語", "x"],
+            split(&fixed_width, "日本語x")
+        );
+        let fixed_width = FixedWidth::from_widths(&[1, 0, 1], true, false);
+        assert_eq!(
+            vec!["日本語", "日", "", "本", "語"],
+            split(&fixed_width, "日本語")
+        );
+        // Counting bytes splits characters, so compare bytes.
+        let fixed_width = FixedWidth::from_widths(&[2], false, false);
+        assert_eq!(
+            vec![&b"\xc3\xa9a"[..], b"\xc3\xa9", b"a"],
+            fixed_width.split("éa".as_bytes())
+        );
+    }
+}
+
+#[cfg(test)]
+mod from_aligned {
+    use super::*;
+
+    fn cut_points(lines: &[&str]) -> Vec<usize> {
+        let lines: Vec<&[u8]> = lines.iter().map(|line| line.as_bytes()).collect();
+        FixedWidth::from_aligned(&lines, false).cut_points
+    }
+
+    #[test]
+    fn header_only() {
+        assert_eq!(vec![7, 13], cut_points(&["NAME   AGE   CITY"]));
+        assert_eq!(vec![6, 10], cut_points(&["  PID TTY CMD"]));
+        assert_eq!(Vec::<usize>::new(), cut_points(&[]));
+        assert_eq!(Vec::<usize>::new(), cut_points(&[""]));
+    }
+
+    #[test]
+    fn values_extending_left_move_the_cut() {
+        assert_eq!(
+            vec![5],
+            cut_points(&["USER   PID", "root 12345", "bin     12"])
+        );
+    }
+
+    #[test]
+    fn values_spanning_a_gap_merge_columns() {
+        assert_eq!(
+            vec![15],
+            cut_points(&["CONTAINER ID   IMAGE", "abc123def456   nginx"])
+        );
+    }
+
+    #[test]
+    fn split() {
+        let header = "NAME   CREATED       STATUS";
+        let line = "web    2 hours ago   Up 2 hours";
+        let fixed_width = FixedWidth::from_aligned(&[header.as_bytes(), line.as_bytes()], false);
+        let expected: Vec<&[u8]> = vec![line.as_bytes(), b"web", b"2 hours ago", b"Up 2 hours"];
+        assert_eq!(expected, fixed_width.split(line.as_bytes()));
+    }
+
+    #[test]
+    fn characters() {
+        let lines: Vec<&[u8]> = vec!["名前 年齢".as_bytes(), "山田 42".as_bytes()];
+        assert_eq!(vec![3], FixedWidth::from_aligned(&lines, true).cut_points);
+    }
+}
@@ -1,19 +1,48 @@
 use clap::Parser;
-use regex::Regex;
 use std::collections::VecDeque;
 use std::fs::File;
-use std::io::BufRead;
 use std::io::BufReader;
+use std::io::IsTerminal;
 use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
 use std::process;
 
+mod csv;
+mod decompress;
+mod delimiter;
+mod fixed_width;
+mod output;
+mod predicate;
+mod records;
+mod template;
+
+use decompress::Decompressor;
+use delimiter::Delimiter;
+use fixed_width::FixedWidth;
+use output::Alignment;
+use output::JsonKey;
+use output::OutputFormat;
+use output::RecordWriter;
+use predicate::Predicate;
+use records::IterRecords;
+use records::ReadAhead;
+use records::ReadRecord;
+use records::RecordSeparator;
+use template::Template;
+
 const ABOUT_TEXT: &str = r#"
 Extract the specified columns from FILES or stdin.
 
 Column numbering starts at 1, not 0; column 0 is the entire line, just like awk.
 Column numbers that are out of bounds are silently ignored.  When each line is
-split, empty leading or trailing columns will be discarded _before_ columns are
-extracted.
+split, empty columns will be discarded _before_ columns are extracted: this
+includes leading and trailing empty columns, e.g. from leading whitespace, and
+interior empty columns, e.g. a::c with -d : has 2 columns.  With --keep-empty no
+columns are discarded, so a::c has 3 columns and :a: has 3 columns, the first
+and last being empty.  Empty lines never have any columns.  --csv always keeps
+empty columns.
 
 Negative column numbers are accepted; -1 is the last column, -2 is the second
 last, etc.  Note that negative column numbers may not behave as you expect when
@@ -22,25 +51,332 @@ column 10, but in line 2 column -1 is column 5.
 You need to put -- before the first negative column number, otherwise it will be
 interpreted as a non-existent option.
 
-Column ranges of the form 3:8, -3:1, 7:-7, and -1:-3 are accepted.  Both start
-and end are required for each range.  It is not an error to specify an end point
-that is out of bounds for a line, so 3:1000 will print all columns from 3
-onwards (unless you have a *very* long line).
+Column ranges of the form 3:8, -3:1, 7:-7, and -1:-3 are accepted.  It is not an
+error to specify an end point that is out of bounds for a line, so 3:1000 will
+print all columns from 3 onwards (unless you have a *very* long line).
+Open-ended ranges of the form 3:, -3:, and :5 extend to the last or first column
+of each line.  Negative start or end points of open-ended ranges are converted
+to column numbers for each line before the range is used, so -3: always prints
+the last 3 columns in order.  Stepped ranges of the form 1:20:2 and -1:-9:3
+print every 2nd or 3rd column of the range; both start and end are required.
+
+Leading arguments are used as column specifiers until an argument isn't one, and
+the remaining arguments are used as filenames.  An argument that starts like a
+column specifier but is malformed, e.g. 1::2 or 3:x, is an error that says where
+the specifier went wrong, unless a file with that name exists.  With -c LIST the
+columns are given as a comma separated list instead, like cut -f, e.g. -c 1,3:5
+or -c NAME,-1 with --header, and every argument is a filename, so files named
+like columns, e.g. 2 or 10:20, can be read.
+
+Columns can be excluded by prefixing a column or range with ^, e.g. ^4, ^2:3, or
+^-1.  Excluded columns are removed from the columns selected by the other
+specifiers, or from all columns if every specifier is an exclusion, so ^-1
+prints every column except the last.  Exclusions are evaluated separately for
+each line.  --complement makes every specifier an exclusion.
+
+The virtual columns F, N, and NR are the name of the file the line was read from
+(- for stdin), the line number within that file, and the line number across all
+files, e.g. colx F N 1 *.log.  They can be used anywhere a column can, but not
+in ranges.  With --header a header column with the same name takes precedence,
+e.g. the F column of ps -l, and virtual columns are labelled with their names in
+the header line.  With --csv they count records rather than lines.  Lines never
+span files, even if a file lacks a trailing newline.
+
+--where PREDICATE only outputs lines matching PREDICATE, which compares a column
+with a value, e.g. 3>100, 1==root, or -1~'^/bin/'.  Predicates can be combined
+with !, &&, ||, and parentheses, and && binds more tightly than ||.  == and !=
+compare strings, ~ and !~ match regexes, and <, <=, >, and >= compare numbers.
+Columns can be numbers, virtual columns, e.g. N>1, or names with --header, and
+comparisons with columns that do not exist in a line never match.  Values
+containing whitespace or any of ()!=<>~&|'" must be quoted with single or double
+quotes.  --where can be repeated and lines must match every predicate.  With
+--header the header line is always output.
+
+With --header the first line is treated as a header and columns can be selected
+by name as well as by number, e.g. NAME STATUS, or NAME:AGE for a range of
+columns.  Names must match a header column exactly and must only appear once in
+the header.  Leading arguments are treated as column specifiers until an
+argument is - or names an existing file.  The header line is output like any
+other line.
+
+--delimiter is a regex, so characters like | and . must be escaped.  With -F the
+delimiter is a fixed string instead, e.g. -F -d '|' or -F -d '::', and lines are
+split with a fast byte search rather than the regex engine.
+
+--pattern REGEX uses the capture groups of REGEX as columns instead of splitting
+each line with --delimiter, e.g.
+--pattern '^\[(.*?)\] user=(?<user>\S+)' 1 user.  Column 1 is the first capture
+group, and named groups can also be selected by name in column specifiers,
+--where, and --format.  Groups that don't participate in the match are empty
+columns.  Lines that don't match are skipped.
+
+--widths and --cut-points split fixed-width input at fixed positions instead of
+splitting each line with --delimiter, so spaces inside a column are kept.
+--widths 8,12,5 gives columns of 8, 12, and 5 bytes, and --cut-points 8,20,25
+cuts each line after bytes 8, 20, and 25, which is equivalent.  The rest of each
+line after the last cut is an extra column, and lines that are too short have
+empty columns.  --characters counts characters rather than bytes, and --trim
+removes the spaces and tabs padding each column.
+
+--aligned splits tables whose columns are aligned with the words in their header
+line, like the output of ps, docker ps, or kubectl get, where values may contain
+spaces, e.g. "2 hours ago".  Each word in the first line starts a column, and
+columns are trimmed.  With --aligned-lines LINES the first LINES lines after the
+header are also used: a column starts further left if a value in those lines
+does, e.g. a right aligned number wider than its header, and columns are merged
+if a value spans the gap between them, e.g. because the header contains
+"CONTAINER ID".  Column positions are found separately for each input, because
+each input has its own header.  --aligned does not imply --header.
+
+Input is split into records at newlines by default.  With -z records are
+separated by NUL instead, in both input and output, e.g. for find -print0.
+--record-separator SEPARATOR works like awk's RS: a single character is used
+literally, an empty string selects paragraph mode where records are separated
+by one or more blank lines, and anything else is a regex.  A separator at the
+end of the input doesn't produce an empty record, and "\r\n" line endings are
+only stripped when records are separated by newlines.
+--output-record-separator SEPARATOR is written after each output record instead
+of newline.
+
+--preserve-delimiters joins each output column to the previous one with the
+original text between them in the line, rather than --separator, when the column
+immediately follows the previous one in the same range, e.g. in 2:5, so the
+spacing and alignment of the input are kept.  Other columns are still joined
+with --separator, including 3 and 4 in 2:3 4.  It only applies to plain output
+without --format or --align, and can't be used with --csv or --pattern.
+
+With --csv the input is parsed as RFC 4180 CSV rather than being split with
+--delimiter: fields are separated by commas and may be quoted with double
+quotes, in which case they can contain commas, double quotes (written as ""),
+and newlines.  Empty fields are kept, and column 0 is the entire record.  Line
+breaks inside quoted fields are kept exactly, including \r\n.
+
+--output-format selects how extracted columns are written: plain joins them with
+--separator, csv quotes them as necessary, tsv separates them with tabs and
+escapes tabs, newlines, carriage returns, and backslashes, and json writes a
+JSON array per line.  With --header, json writes an object per line keyed by
+header names instead, and the header line is not output.
+
+Input is processed as bytes, so input that is not valid UTF-8 is passed through
+unchanged, except that json output replaces invalid UTF-8 with U+FFFD
+REPLACEMENT CHARACTER.  With --lossy invalid UTF-8 in each line is replaced with
+U+FFFD before the line is split.
+
+--align pads output columns so that they line up like a table, using the display
+width of each column so that e.g. CJK characters line up correctly.  Columns are
+left aligned by default; --align=right,left,numeric right aligns the first
+column, left aligns the second, and right aligns numbers and left aligns
+everything else in the remaining columns.  All input is read before anything is
+output unless --align-buffer LINES is used, in which case lines are output in
+batches of LINES and column widths grow as wider columns are seen.
+
+--format TEMPLATE outputs each line using TEMPLATE rather than joining the
+columns, e.g. --format '{1}@{3}:{-1}' or --format '{NAME}={2:4}'.  Each
+placeholder in braces contains a column specifier, which is replaced with the
+matching columns joined with --separator.  {{ and }} are literal braces, and
+\n, \t, and \\ are a newline, a tab, and a backslash.  With --format all
+arguments are used as filenames.
+
+Inputs compressed with gzip, zstd, xz, or bzip2 are decompressed transparently,
+including stdin.  Compression is detected from the contents of each input rather
+than its name, so compressed and uncompressed files can be mixed freely.
+
+Files are opened one at a time as they are read, so there is no limit on the
+number of files.  Like cat, a file that can't be read is reported when it is
+reached and the remaining files are processed, then colx exits with status 2.
+Errors include the file name, and errors while reading a file also include the
+line number, e.g. "colx: access.log.gz:1234: invalid gzip header".
+With --check-files every file is opened before anything is read, so nothing is
+output if any file can't be opened.  --strict restores the behaviour of older
+versions: it implies --check-files, and colx exits with status 1 at the first
+error while reading a file, after outputting the lines read before the error.
+
+Output is buffered unless stdout is a terminal, so output to a pipe appears in
+blocks rather than line by line.  If the reader of the pipe exits, e.g. with
+colx 1 | head, colx exits quietly with status 141, like a process killed by
+SIGPIPE.
 "#;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = ABOUT_TEXT)]
 struct Flags {
+    /// Comma separated column specifiers, e.g. -c 1,3:5,-1, like cut -f.  All arguments are used
+    /// as filenames.
+    #[arg(
+        short,
+        long,
+        value_name = "LIST",
+        allow_hyphen_values = true,
+        conflicts_with = "format"
+    )]
+    columns: Option<String>,
+
     /// Regex delimiting input columns; defaults to whitespace.
     #[arg(short, long, default_value = "\\s+")]
     delimiter: String,
 
-    /// Separator between output columns; defaults to a single space.
+    /// Treat --delimiter as a fixed string rather than a regex, e.g. -F -d '|'.
+    #[arg(short = 'F', long, requires = "delimiter")]
+    fixed_delimiter: bool,
+
+    /// Separator between output columns; defaults to a single space.  Only used by the plain
+    /// output format.
     #[arg(short, long, default_value = " ")]
     separator: String,
 
+    /// Join consecutive columns from the same range with the original text between them rather
+    /// than --separator, e.g. to keep the alignment of 2:5.
+    #[arg(
+        long,
+        conflicts_with_all = ["output_format", "format", "align", "csv", "pattern"]
+    )]
+    preserve_delimiters: bool,
+
+    /// Format for output lines.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    output_format: OutputFormat,
+
+    /// Output each line using TEMPLATE, e.g. '{1}@{3}:{-1}'; see --help for details.  All
+    /// arguments are used as filenames.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["output_format", "align", "complement"]
+    )]
+    format: Option<String>,
+
+    /// Pad output columns so that they line up, aligning each column as specified by a comma
+    /// separated list; the last alignment is used for remaining columns.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALIGNMENTS",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ',',
+        default_missing_value = "left",
+        conflicts_with = "output_format"
+    )]
+    align: Option<Vec<Alignment>>,
+
+    /// With --align, output lines in batches of LINES rather than reading all input before
+    /// outputting anything.
+    #[arg(
+        long,
+        value_name = "LINES",
+        requires = "align",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    align_buffer: Option<u64>,
+
+    /// Only output lines matching PREDICATE, e.g. '3>100 && 1~^web'; see --help for details.  Can
+    /// be repeated, and lines must match every predicate.
+    #[arg(long = "where", value_name = "PREDICATE", allow_hyphen_values = true)]
+    predicates: Vec<String>,
+
+    /// Print all columns except the specified columns.
+    #[arg(long)]
+    complement: bool,
+
+    /// Treat the first line as a header so that columns can be selected by name.
+    #[arg(long)]
+    header: bool,
+
+    /// Use the capture groups of REGEX as columns instead of splitting with --delimiter; named
+    /// groups can be selected by name.  Lines that don't match are skipped.
+    #[arg(long, value_name = "REGEX", conflicts_with_all = ["delimiter", "csv", "header"])]
+    pattern: Option<String>,
+
+    /// Split fixed-width input into columns with the given widths, e.g. 8,12,5; the rest of each
+    /// line is the last column.
+    #[arg(
+        long,
+        value_name = "WIDTHS",
+        value_delimiter = ',',
+        group = "fixed_width",
+        conflicts_with_all = ["delimiter", "csv", "pattern"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    widths: Option<Vec<u64>>,
+
+    /// Split fixed-width input into columns by cutting each line after the given positions, e.g.
+    /// 8,20,25.
+    #[arg(
+        long,
+        value_name = "POSITIONS",
+        value_delimiter = ',',
+        group = "fixed_width",
+        conflicts_with_all = ["delimiter", "csv", "pattern"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    cut_points: Option<Vec<u64>>,
+
+    /// Split a table whose columns are aligned with the words in its header line, like the output of
+    /// ps or kubectl get, at the positions of the header words; see --help for details.
+    #[arg(long, group = "fixed_width", conflicts_with_all = ["delimiter", "csv", "pattern"])]
+    aligned: bool,
+
+    /// With --aligned, also use the first LINES lines after the header to find where columns
+    /// start, so that values wider than their header or header names containing spaces are
+    /// handled.
+    #[arg(
+        long,
+        value_name = "LINES",
+        requires = "aligned",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    aligned_lines: Option<u64>,
+
+    /// Count --widths, --cut-points, and --aligned positions in characters rather than bytes.
+    #[arg(long, requires = "fixed_width")]
+    characters: bool,
+
+    /// Remove spaces and tabs padding fixed-width columns.
+    #[arg(long, requires = "fixed_width")]
+    trim: bool,
+
+    /// Input and output records are separated by NUL rather than newline, e.g. for find -print0.
+    #[arg(short = 'z', long, conflicts_with_all = ["record_separator", "csv"])]
+    null_data: bool,
+
+    /// Separator between input records: a single character, a regex, or an empty string for
+    /// paragraph mode where records are separated by blank lines; defaults to newline.
+    #[arg(
+        long,
+        value_name = "SEPARATOR",
+        allow_hyphen_values = true,
+        conflicts_with = "csv"
+    )]
+    record_separator: Option<String>,
+
+    /// Separator written after each output record; defaults to newline, or NUL with -z.
+    #[arg(long, value_name = "SEPARATOR", allow_hyphen_values = true)]
+    output_record_separator: Option<String>,
+
+    /// Parse input as CSV with quoted fields instead of splitting with --delimiter.
+    #[arg(long, conflicts_with = "delimiter")]
+    csv: bool,
+
+    /// Keep empty columns rather than discarding them when splitting lines.
+    #[arg(long)]
+    keep_empty: bool,
+
+    /// Replace invalid UTF-8 in input with U+FFFD REPLACEMENT CHARACTER.
+    #[arg(long)]
+    lossy: bool,
+
+    /// Check that all files can be opened before reading any of them.
+    #[arg(long)]
+    check_files: bool,
+
+    /// Exit at the first file that can't be read rather than continuing with the remaining files.
+    /// Implies --check-files.
+    #[arg(long)]
+    strict: bool,
+
     /// Leading arguments that look like column specifiers are used as
-    /// column specifiers, then remaining arguments are used as filenames.
+    /// column specifiers, then remaining arguments are used as filenames.  With
+    /// --columns or --format all arguments are filenames.
     #[arg()]
     columns_then_files: Vec<String>,
 }
@@ -48,56 +384,154 @@ struct Flags {
 /// Read from all the provided files, reading from the next file when the end of the current file
 /// is reached.  Reads from Stdin if a filename is "-".
 struct MultipleFileReader {
-    filehandles: VecDeque<Box<dyn Read>>,
+    inputs: VecDeque<Input>,
+}
+
+/// An input to MultipleFileReader, with its name and the number of lines read so far for error
+/// messages.
+struct Input {
+    name: String,
+    state: InputState,
+    lines: usize,
+}
+
+/// Files are opened when reading reaches them, so that only one file is open at a time.
+enum InputState {
+    Unopened,
+    Open(Box<dyn Read>),
+}
+
+impl Input {
+    fn unopened(name: String) -> Self {
+        Self {
+            name,
+            state: InputState::Unopened,
+            lines: 0,
+        }
+    }
+
+    fn open(name: String, handle: Box<dyn Read>) -> Self {
+        Self {
+            name,
+            state: InputState::Open(handle),
+            lines: 0,
+        }
+    }
+}
+
+/// Reads from the input, opening it first if necessary.  Errors from opening are prefixed with
+/// the name of the input, and errors from reading are prefixed with the name and line number.
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let InputState::Unopened = self.state {
+            let filehandle = Box::new(open_file(&self.name)?);
+            self.state = InputState::Open(Box::new(Decompressor::new(filehandle)));
+        }
+        let InputState::Open(handle) = &mut self.state else {
+            unreachable!("input was opened above");
+        };
+        match handle.read(buf) {
+            Ok(length) => {
+                self.lines += memchr::memchr_iter(b'\n', &buf[..length]).count();
+                Ok(length)
+            }
+            Err(e) => Err(std::io::Error::new(
+                e.kind(),
+                format!("{}:{}: {}", self.name, self.lines + 1, describe_error(&e)),
+            )),
+        }
+    }
+}
+
+/// Formats an error like coreutils formats errors, e.g. "No such file or directory", by stripping
+/// the " (os error 2)" suffix that std adds to OS errors.
+fn describe_error(e: &std::io::Error) -> String {
+    let mut message = e.to_string();
+    if let Some(code) = e.raw_os_error() {
+        let suffix = format!(" (os error {code})");
+        if message.ends_with(&suffix) {
+            message.truncate(message.len() - suffix.len());
+        }
+    }
+    message
+}
+
+/// Opens a file, adding the filename to any error because the error from
+/// [File::open](std::fs::File::open) doesn't include it, e.g. "file: No such file or directory".
+fn open_file(filename: &str) -> Result<File, std::io::Error> {
+    File::open(filename)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{filename}: {}", describe_error(&e))))
 }
 
 impl MultipleFileReader {
     /// Initialises and returns a MultipleFileReader from a list of filenames.
     ///
-    /// All the filenames provided will be opened eagerly, so problems related to permissions or
-    /// existence will be detected by new and the error from [File::open](std::fs::File::open) will
-    /// be returned.
+    /// Files are opened lazily when reading reaches them, so problems related to permissions or
+    /// existence will be returned by read.  If check_files is true every file is also opened and
+    /// closed by new, so those problems will be detected upfront and the error from
+    /// [File::open](std::fs::File::open) will be returned.
     ///
     /// A filename of "-" will use [std::io::stdin] to open stdin.
     ///
+    /// Inputs compressed with gzip, zstd, xz, or bzip2 (including stdin) are detected by their
+    /// magic bytes and decompressed transparently.
+    ///
     /// If filenames is empty a filename of "-" will be used instead.  This supports the standard
     /// Unix idiom of "if filenames are given read them, else read from stdin".
-    fn new(filenames: Vec<String>) -> Result<Self, std::io::Error> {
-        Self::new_with_opener(filenames, std::io::stdin)
+    fn new(filenames: Vec<String>, check_files: bool) -> Result<Self, std::io::Error> {
+        Self::new_with_opener(filenames, check_files, std::io::stdin)
     }
 
     // This function allows tests to use dependency injection to check that the correct function is
     // called when the filename is "-".  This is not intended for others to use.
     fn new_with_opener<Closure>(
         filenames: Vec<String>,
+        check_files: bool,
         mut stdin_opener: Closure,
     ) -> Result<Self, std::io::Error>
     where
         Closure: FnMut() -> std::io::Stdin,
     {
         if filenames.is_empty() {
-            return Self::new_with_opener(vec![String::from("-")], stdin_opener);
+            return Self::new_with_opener(vec![String::from("-")], check_files, stdin_opener);
         }
 
-        let mut filehandles: Vec<Box<dyn Read>> = Vec::with_capacity(filenames.len());
+        let mut inputs = VecDeque::with_capacity(filenames.len());
         for filename in filenames {
             if filename == "-" {
-                filehandles.push(Box::new(stdin_opener()));
+                // Opening stdin doesn't use a file descriptor, so there's no need to defer it.
+                let filehandle = Box::new(stdin_opener());
+                let filehandle = Box::new(Decompressor::new(filehandle));
+                inputs.push_back(Input::open(filename, filehandle));
             } else {
-                filehandles.push(Box::new(File::open(filename)?));
+                if check_files {
+                    open_file(&filename)?;
+                }
+                inputs.push_back(Input::unopened(filename));
             }
         }
-        Ok(Self::new_from_filehandles(filehandles))
+        Ok(Self { inputs })
     }
 
     /// Initialises and returns a MultipleFileReader from a list of filehandles (anything
     /// implementing the [std::io::Read] trait).  Uses the filehandles unchanged, so they can
-    /// point to anything: files, stdin, sockets, ...
-    fn new_from_filehandles(filehandles: Vec<Box<dyn Read>>) -> MultipleFileReader {
+    /// point to anything: files, stdin, sockets, ...  Each filehandle is paired with the name used
+    /// in error messages.
+    #[cfg(test)]
+    fn new_from_filehandles(filehandles: Vec<(String, Box<dyn Read>)>) -> MultipleFileReader {
         Self {
-            filehandles: VecDeque::from(filehandles),
+            inputs: filehandles
+                .into_iter()
+                .map(|(name, handle)| Input::open(name, handle))
+                .collect(),
         }
     }
+
+    /// Removes and returns the next input, so that it can be read separately from the other inputs,
+    /// e.g. to track which input each line comes from.  Returns None when there are no more inputs.
+    fn next_input(&mut self) -> Option<Input> {
+        self.inputs.pop_front()
+    }
 }
 
 /// Implements the [std::io::Read] trait for MultipleFileReader.
@@ -108,56 +542,448 @@ impl Read for MultipleFileReader {
     ///   be retried.
     /// - The current input filehandle will be discarded when moving on to the next input, so it
     ///   will automatically be closed.
-    /// - Errors from underlying read() calls are returned *without* advancing to the next input.
-    ///   read() will return errors while the underlying read() call continues to return errors.
+    /// - Files are opened when reading reaches them.
+    /// - Errors from opening files and from underlying read() calls are returned *without*
+    ///   advancing to the next input.  read() will return errors while opening the file or the
+    ///   underlying read() call continues to return errors.
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        while let Some(handle) = self.filehandles.front_mut() {
-            let length = handle.read(buf)?;
+        while let Some(input) = self.inputs.front_mut() {
+            let length = input.read(buf)?;
             if length > 0 {
                 return Ok(length);
             }
             // Filehandle has run out of data.
-            self.filehandles.pop_front();
+            self.inputs.pop_front();
         }
         // Run out of files to read.
         Ok(0)
     }
 }
 
-// Holds a single column range, start-end inclusive, with start==end for single column ranges.
+// Columns that don't come from splitting the line: the name of the input the line was read from
+// ("-" for stdin), the line number within that input, and the line number across all inputs.  With
+// --csv the line numbers count records rather than lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VirtualColumn {
+    Filename,
+    LineNumber,
+    RecordNumber,
+}
+
+impl VirtualColumn {
+    // All the virtual columns, in the order their values are passed to extract_columns().
+    const ALL: [VirtualColumn; 3] = [
+        VirtualColumn::Filename,
+        VirtualColumn::LineNumber,
+        VirtualColumn::RecordNumber,
+    ];
+
+    // The name used in column specifiers, which is also the key used for JSON output.
+    fn name(self) -> &'static str {
+        match self {
+            VirtualColumn::Filename => "F",
+            VirtualColumn::LineNumber => "N",
+            VirtualColumn::RecordNumber => "NR",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|column| column.name() == name)
+    }
+}
+
+// Holds a single column range, start-end inclusive, with start==end for single column ranges.  A
+// missing start or end makes the range open-ended, extending to the first or last column of each
+// line.  Every step'th column of the range is used.  Columns in ranges with exclude set are removed
+// from the output rather than added to it.  A range with virtual_column set selects that virtual
+// column instead, and start, end, and step are unused.
 #[derive(Debug, PartialEq)]
 struct ColumnRange {
-    start: isize,
-    end: isize,
+    start: Option<isize>,
+    end: Option<isize>,
+    step: usize,
+    exclude: bool,
+    virtual_column: Option<VirtualColumn>,
 }
 
-// Parse a string that *might* represent a column range.
-fn parse_column_range(maybe_column: &str) -> Option<ColumnRange> {
-    if let Ok(single_column) = maybe_column.parse::<isize>() {
-        return Some(ColumnRange {
-            start: single_column,
-            end: single_column,
-        });
+impl ColumnRange {
+    // Returns a range from start to end inclusive with a step of 1.  Only tests need this.
+    #[cfg(test)]
+    fn new(start: isize, end: isize) -> Self {
+        Self {
+            start: Some(start),
+            end: Some(end),
+            step: 1,
+            exclude: false,
+            virtual_column: None,
+        }
     }
 
-    let regex = Regex::new(r"^(-?\d+):(-?\d+)$").unwrap();
-    if let Some(matches) = regex.captures(maybe_column) {
-        return Some(ColumnRange {
-            start: matches[1].parse::<isize>().ok()?,
-            end: matches[2].parse::<isize>().ok()?,
-        });
+    // Returns a range selecting a single virtual column.
+    fn virtual_column(column: VirtualColumn, exclude: bool) -> Self {
+        Self {
+            start: None,
+            end: None,
+            step: 1,
+            exclude,
+            virtual_column: Some(column),
+        }
+    }
+
+    // Returns the column numbers in this range for a line with num_columns columns (including column
+    // 0, the whole line).  Column numbers from ranges with both start and end may be negative or out
+    // of bounds, so they must be resolved with resolve_index(), but numbers beyond -num_columns or
+    // num_columns can never be resolved and are skipped, so huge ranges like 1:9999999999 are cheap.
+    // Open-ended ranges are first converted to the equivalent non-negative range for this line, and
+    // produce no columns if the fixed end is beyond the open end, e.g. 5: on a line with 3 columns.
+    // The numbers are generated rather than collected so that extracting columns doesn't allocate.
+    // The arithmetic is done with i128 so that no range or step can overflow it.
+    fn column_numbers(&self, num_columns: usize) -> impl Iterator<Item = isize> + use<> {
+        let limit = i128::try_from(num_columns).unwrap();
+        let step = i128::try_from(self.step).unwrap();
+        let wide = |number: isize| i128::try_from(number).unwrap();
+        // Moves start towards end by the smallest multiple of step that is at least distance.
+        let skip = |distance: i128| (distance.max(0) + step - 1) / step * step;
+        let (start, end, direction) = match (self.start, self.end) {
+            (Some(start), Some(end)) if start <= end => {
+                let (start, end) = (wide(start), wide(end));
+                (start + skip(-limit - start), end.min(limit), 1)
+            }
+            (Some(start), Some(end)) => {
+                let (start, end) = (wide(start), wide(end));
+                (start - skip(start - limit), end.max(-limit), -1)
+            }
+            (start, end) => {
+                let start = match start.map(wide) {
+                    None => 1,
+                    Some(start) if start < 0 => (start + limit).max(1),
+                    Some(start) => start,
+                };
+                let end = match end.map(wide) {
+                    None => limit - 1,
+                    Some(end) if end < 0 => end + limit,
+                    Some(end) => end.min(limit - 1),
+                };
+                (start, end, 1)
+            }
+        };
+        let distance = (end - start) * direction;
+        let count = if distance < 0 { 0 } else { distance / step + 1 };
+        (0..count).map(move |i| isize::try_from(start + direction * i * step).unwrap())
+    }
+}
+
+// Parse a string that *might* represent a column range using column numbers, or a virtual column.
+fn parse_column_range(maybe_column: &str) -> Result<ColumnRange, ColumnSpecError> {
+    let named_range = parse_column_specifier(maybe_column, false)?;
+    if let Some(column_range) = virtual_column_range(&named_range) {
+        return Ok(column_range);
+    }
+    let number = |bound: Option<ColumnBound>| match bound {
+        Some(ColumnBound::Number(number)) => Some(number),
+        _ => None,
+    };
+    Ok(ColumnRange {
+        start: number(named_range.start),
+        end: number(named_range.end),
+        step: named_range.step,
+        exclude: named_range.exclude,
+        virtual_column: None,
+    })
+}
+
+// Returns a range selecting a virtual column if named_range is a single column whose name is a
+// virtual column name, e.g. F.  resolve_column_names() gives header column names precedence.
+fn virtual_column_range(named_range: &NamedColumnRange) -> Option<ColumnRange> {
+    match (&named_range.start, &named_range.end) {
+        (Some(ColumnBound::Name(start)), Some(ColumnBound::Name(end))) if start == end => {
+            VirtualColumn::from_name(start)
+                .map(|column| ColumnRange::virtual_column(column, named_range.exclude))
+        }
+        _ => None,
+    }
+}
+
+// One end of a column specifier given with --header: either a column number or a column name that
+// will be looked up in the header line.
+#[derive(Clone, Debug, PartialEq)]
+enum ColumnBound {
+    Number(isize),
+    Name(String),
+}
+
+// Holds a single column range that may use column names, with the same meaning as ColumnRange.
+// Resolved to a ColumnRange by resolve_column_names().
+#[derive(Debug, PartialEq)]
+struct NamedColumnRange {
+    start: Option<ColumnBound>,
+    end: Option<ColumnBound>,
+    step: usize,
+    exclude: bool,
+}
+
+// The reason a column specifier couldn't be parsed: the byte offset where parsing failed, and what
+// was expected there.
+#[derive(Debug, PartialEq)]
+struct ColumnSpecError {
+    specifier: String,
+    position: usize,
+    expected: &'static str,
+}
+
+impl std::fmt::Display for ColumnSpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // People count characters from 1, not bytes from 0.
+        let character = self.specifier[..self.position].chars().count() + 1;
+        write!(f, "expected {} at character {character}", self.expected)
     }
+}
+
+// Parse a string that *might* represent a column range using column names and/or numbers.
+fn parse_named_column_range(maybe_column: &str) -> Result<NamedColumnRange, ColumnSpecError> {
+    parse_column_specifier(maybe_column, true)
+}
 
-    None
+// Parse a column specifier: a single column, start:end, open-ended start: and :end, or stepped
+// start:end:step, any of which can be prefixed with ^ to exclude the columns.  Each column is a
+// number, optionally negative, or a name if allow_names is true; a single column can always be a
+// virtual column name.  The specifier is split into fields at colons and each field is parsed by
+// hand so that errors can say where the specifier went wrong.
+fn parse_column_specifier(
+    specifier: &str,
+    allow_names: bool,
+) -> Result<NamedColumnRange, ColumnSpecError> {
+    let error = |position: usize, expected: &'static str| ColumnSpecError {
+        specifier: String::from(specifier),
+        position,
+        expected,
+    };
+    let expected_column = if allow_names {
+        "a column number or name"
+    } else {
+        "a column number"
+    };
+    let exclude = specifier.starts_with('^');
+    // Each field with the position it starts at.
+    let mut fields: Vec<(usize, &str)> = vec![];
+    let mut field_start = usize::from(exclude);
+    for (i, byte) in specifier.bytes().enumerate().skip(field_start) {
+        if byte == b':' {
+            fields.push((field_start, &specifier[field_start..i]));
+            field_start = i + 1;
+        }
+    }
+    fields.push((field_start, &specifier[field_start..]));
+    // Parse one end of a range, where an empty field means the range is open at that end.  Anything
+    // that looks like a number must be a valid column number.
+    let bound = |(position, field): (usize, &str), names: bool| {
+        let digits = field.strip_prefix('-').unwrap_or(field);
+        if field.is_empty() {
+            Ok(None)
+        } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            match field.parse::<isize>() {
+                Ok(number) => Ok(Some(ColumnBound::Number(number))),
+                Err(_) => Err(error(position, "a column number with fewer digits")),
+            }
+        } else if names {
+            Ok(Some(ColumnBound::Name(String::from(field))))
+        } else {
+            Err(error(position, expected_column))
+        }
+    };
+    let required = |field: (usize, &str), names: bool| {
+        bound(field, names)?.ok_or_else(|| error(field.0, expected_column))
+    };
+    let (start, end, step) = match fields[..] {
+        [single] => {
+            let names = allow_names || VirtualColumn::from_name(single.1).is_some();
+            let column = required(single, names)?;
+            (Some(column.clone()), Some(column), 1)
+        }
+        [start, end] => match (bound(start, allow_names)?, bound(end, allow_names)?) {
+            (None, None) => return Err(error(end.0, expected_column)),
+            (start, end) => (start, end, 1),
+        },
+        [start, end, (position, step)] => {
+            let start = required(start, allow_names)?;
+            let end = required(end, allow_names)?;
+            let step = Some(step)
+                .filter(|step| step.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|step| step.parse::<usize>().ok())
+                .filter(|step| *step > 0)
+                .ok_or_else(|| error(position, "a positive step"))?;
+            (Some(start), Some(end), step)
+        }
+        // The colon before the fourth field is the mistake.
+        [_, _, _, (position, _), ..] => {
+            return Err(error(position - 1, "the end of the column specifier"));
+        }
+        [] => unreachable!("there is always at least one field"),
+    };
+    Ok(NamedColumnRange {
+        start,
+        end,
+        step,
+        exclude,
+    })
 }
 
 // Split a list of arguments into leading column ranges and remaining filenames.  Returns parsed
-// column ranges and untouched filenames.  This short function is standalone rather than inlined
-// into realmain() because it's easier to test in isolation.
-fn separate_args(mut args: Vec<String>) -> (Vec<ColumnRange>, Vec<String>) {
-    let columns: Vec<ColumnRange> = args.iter().map_while(|x| parse_column_range(x)).collect();
+// column ranges and untouched filenames.  An argument that starts like a column specifier but is
+// malformed, e.g. 1::2 or ^x, is an error rather than a filename unless it names an existing file,
+// so that typos are reported clearly.  This short function is standalone rather than inlined into
+// realmain() because it's easier to test in isolation.
+fn separate_args(mut args: Vec<String>) -> Result<(Vec<ColumnRange>, Vec<String>), String> {
+    let mut columns = vec![];
+    for arg in args.iter() {
+        match parse_column_range(arg) {
+            Ok(column_range) => columns.push(column_range),
+            Err(error) if error.position > 0 && !Path::new(arg).exists() => {
+                return Err(format!("Invalid column specifier \"{arg}\": {error}"));
+            }
+            Err(_) => break,
+        }
+    }
     let filenames = args.split_off(columns.len());
-    (columns, filenames)
+    Ok((columns, filenames))
+}
+
+// Like separate_args(), but for --header, where any argument might be a column name.  Leading
+// arguments are used as column specifiers until an argument is "-" or names an existing file, then
+// remaining arguments are used as filenames.  Every column specifier must be valid.
+fn separate_named_args(
+    mut args: Vec<String>,
+) -> Result<(Vec<NamedColumnRange>, Vec<String>), String> {
+    let mut columns = vec![];
+    for arg in args.iter() {
+        if parse_column_range(arg).is_err() && (arg == "-" || Path::new(arg).exists()) {
+            break;
+        }
+        match parse_named_column_range(arg) {
+            Ok(named_range) => columns.push(named_range),
+            Err(error) => return Err(format!("Invalid column specifier \"{arg}\": {error}")),
+        }
+    }
+    let filenames = args.split_off(columns.len());
+    Ok((columns, filenames))
+}
+
+// Look up a column bound in the header columns, returning the column number.  header[0] must be the
+// whole header line, and is never matched by name.
+fn resolve_column_bound<T: AsRef<[u8]> + ?Sized>(
+    bound: &ColumnBound,
+    header: &[&T],
+) -> Result<isize, String> {
+    let name = match bound {
+        ColumnBound::Number(number) => return Ok(*number),
+        ColumnBound::Name(name) => name,
+    };
+    let matches: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, column)| column.as_ref() == name.as_bytes())
+        .map(|(i, _)| i)
+        .collect();
+    match matches[..] {
+        [] => Err(format!("Column name \"{name}\" not found in header.")),
+        [i] => Ok(i.try_into().unwrap()),
+        _ => Err(format!(
+            "Column name \"{name}\" is ambiguous: it matches header columns {}.",
+            matches
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+// Resolve column names in named_ranges using the header columns, returning plain column ranges.
+// Returns an error message for the first column name that is missing from the header or matches
+// more than one header column.  A virtual column name selects the virtual column only if no header
+// column has that name, so that e.g. the F column of ps -l can still be selected.
+fn resolve_column_names<T: AsRef<[u8]> + ?Sized>(
+    named_ranges: &[NamedColumnRange],
+    header: &[&T],
+) -> Result<Vec<ColumnRange>, String> {
+    let resolve = |bound: &Option<ColumnBound>| match bound {
+        None => Ok(None),
+        Some(bound) => resolve_column_bound(bound, header).map(Some),
+    };
+    named_ranges
+        .iter()
+        .map(|named_range| {
+            if let Some(column_range) = virtual_column_range(named_range)
+                && let Some(ColumnBound::Name(name)) = &named_range.start
+                && !header
+                    .iter()
+                    .skip(1)
+                    .any(|column| column.as_ref() == name.as_bytes())
+            {
+                return Ok(column_range);
+            }
+            Ok(ColumnRange {
+                start: resolve(&named_range.start)?,
+                end: resolve(&named_range.end)?,
+                step: named_range.step,
+                exclude: named_range.exclude,
+                virtual_column: None,
+            })
+        })
+        .collect()
+}
+
+// Parse the comma separated column specifiers given with --columns.  Returns parsed column ranges,
+// or parsed named column ranges if header is true.  Error positions count from the start of list
+// rather than the start of the specifier.
+fn parse_column_list(
+    list: &str,
+    header: bool,
+) -> Result<(Vec<ColumnRange>, Vec<NamedColumnRange>), String> {
+    let mut column_ranges = vec![];
+    let mut named_ranges = vec![];
+    let mut offset = 0;
+    for specifier in list.split(',') {
+        let parsed = if header {
+            parse_named_column_range(specifier).map(|named_range| named_ranges.push(named_range))
+        } else {
+            parse_column_range(specifier).map(|column_range| column_ranges.push(column_range))
+        };
+        if let Err(error) = parsed {
+            let error = ColumnSpecError {
+                specifier: String::from(list),
+                position: offset + error.position,
+                expected: error.expected,
+            };
+            return Err(format!("Invalid --columns list \"{list}\": {error}"));
+        }
+        offset += specifier.len() + 1;
+    }
+    Ok((column_ranges, named_ranges))
+}
+
+// Parse the column specifiers from the placeholders in a --format template.  Returns parsed column
+// ranges, or parsed named column ranges if header is true.
+fn parse_placeholders(
+    placeholders: &[String],
+    header: bool,
+) -> Result<(Vec<ColumnRange>, Vec<NamedColumnRange>), String> {
+    let invalid = |placeholder: &String, error: ColumnSpecError| {
+        format!("Invalid placeholder {{{placeholder}}} in --format: {error}")
+    };
+    let mut column_ranges = vec![];
+    let mut named_ranges = vec![];
+    for placeholder in placeholders {
+        if header {
+            named_ranges
+                .push(parse_named_column_range(placeholder).map_err(|e| invalid(placeholder, e))?);
+        } else {
+            column_ranges
+                .push(parse_column_range(placeholder).map_err(|e| invalid(placeholder, e))?);
+        }
+    }
+    Ok((column_ranges, named_ranges))
 }
 
 // Resolves a possibly negative index into a valid `usize` index for a slice of the given length.
@@ -180,31 +1006,306 @@ fn resolve_index(i: isize, len: usize) -> Option<usize> {
     Some(k)
 }
 
-// Extract and return the columns specified by column_ranges from the input columns.  Out of bounds
-// columns will be silently ignored.  The input columns must live for as long as the returned
-// columns, because references are returned rather than copies.  To meet user expectations,
-// columns[0] must be the whole input line.
-fn extract_columns<'a>(column_ranges: &[ColumnRange], columns: &'a [&'a str]) -> Vec<&'a str> {
-    let mut results = vec![];
-    for column_range in column_ranges.iter() {
-        let indices: Vec<isize> = if column_range.start < column_range.end {
-            (column_range.start..=column_range.end).collect()
+// Return the indices of the columns specified by column_ranges for a line with num_columns columns
+// (including column 0, the whole line).  Out of bounds columns will be silently ignored.  Columns in
+// excluded ranges are removed from the columns specified by the other ranges, or from all columns
+// except column 0 if every range is excluded.  Virtual columns follow the real columns, so the index
+// of a virtual column is num_columns plus its position in VirtualColumn::ALL.
+fn extract_indices(column_ranges: &[ColumnRange], num_columns: usize) -> Vec<usize> {
+    let mut buffers = IndexBuffers::default();
+    extract_indices_into(column_ranges, num_columns, &mut buffers);
+    buffers.results
+}
+
+// Buffers for extract_indices_into(), which are reused for every line so that extracting columns
+// doesn't allocate.
+#[derive(Default)]
+struct IndexBuffers {
+    results: Vec<usize>,
+    // The position in column_ranges of the range that each index in results came from, or
+    // column_ranges.len() for the columns selected when every range is excluded.
+    sources: Vec<usize>,
+    excluded: Vec<usize>,
+}
+
+// Like extract_indices(), but the indices are stored in buffers.results and the ranges they came
+// from in buffers.sources, replacing their contents.
+fn extract_indices_into(
+    column_ranges: &[ColumnRange],
+    num_columns: usize,
+    buffers: &mut IndexBuffers,
+) {
+    let IndexBuffers {
+        results,
+        sources,
+        excluded,
+    } = buffers;
+    results.clear();
+    sources.clear();
+    excluded.clear();
+    for (source, column_range) in column_ranges.iter().enumerate() {
+        let indices = if column_range.exclude {
+            &mut *excluded
         } else {
-            (column_range.end..=column_range.start).rev().collect()
+            &mut *results
         };
-        for i in indices {
-            if let Some(k) = resolve_index(i, columns.len()) {
-                results.push(columns[k]);
+        if let Some(column) = column_range.virtual_column {
+            indices.push(num_columns + column as usize);
+        } else {
+            for i in column_range.column_numbers(num_columns) {
+                if let Some(k) = resolve_index(i, num_columns) {
+                    indices.push(k);
+                }
             }
         }
+        if !column_range.exclude {
+            sources.resize(results.len(), source);
+        }
+    }
+    if !column_ranges.is_empty()
+        && column_ranges
+            .iter()
+            .all(|column_range| column_range.exclude)
+    {
+        results.extend(1..num_columns);
+        sources.resize(results.len(), column_ranges.len());
+    }
+    let mut kept = 0;
+    for i in 0..results.len() {
+        if !excluded.contains(&results[i]) {
+            results[kept] = results[i];
+            sources[kept] = sources[i];
+            kept += 1;
+        }
+    }
+    results.truncate(kept);
+    sources.truncate(kept);
+}
+
+// The columns of a line: column k is text[spans[k]], and column 0 is the whole line.  Lines are
+// split into the positions of their columns rather than a list of columns so that the positions can
+// be kept in a buffer that is reused for every line.  text is the line itself, except with --csv,
+// where it holds the line followed by the unquoted fields.
+#[derive(Clone, Copy)]
+struct Columns<'a> {
+    text: &'a [u8],
+    spans: &'a [Range<usize>],
+}
+
+impl<'a> Columns<'a> {
+    fn new(text: &'a [u8], spans: &'a [Range<usize>]) -> Self {
+        Self { text, spans }
+    }
+
+    fn len(self) -> usize {
+        self.spans.len()
+    }
+
+    // Returns column k, which must be in bounds.
+    fn get(self, k: usize) -> &'a [u8] {
+        &self.text[self.spans[k].clone()]
+    }
+
+    // Returns the column with index k from extract_indices(), which is a virtual column if k is
+    // past the end of the columns.  virtual_columns holds the values of the virtual columns in the
+    // order of VirtualColumn::ALL.
+    fn get_extracted(self, k: usize, virtual_columns: &[&'a [u8]]) -> &'a [u8] {
+        match k.checked_sub(self.len()) {
+            Some(v) => virtual_columns[v],
+            None => self.get(k),
+        }
+    }
+
+    fn iter(self) -> impl Iterator<Item = &'a [u8]> {
+        (0..self.len()).map(move |k| self.get(k))
+    }
+}
+
+// Returns the text and spans for Columns holding columns, so that tests can build Columns.
+#[cfg(test)]
+fn text_and_spans<T: AsRef<[u8]>>(columns: &[T]) -> (Vec<u8>, Vec<Range<usize>>) {
+    let mut text = vec![];
+    let mut spans = vec![];
+    for column in columns {
+        let start = text.len();
+        text.extend_from_slice(column.as_ref());
+        spans.push(start..text.len());
+    }
+    (text, spans)
+}
+
+// Extract and return the columns specified by column_ranges from the input columns.  Out of bounds
+// columns will be silently ignored.  To meet user expectations, columns[0] must be the whole input
+// line.  virtual_columns holds the values of the virtual columns in the order of
+// VirtualColumn::ALL, and may be empty if column_ranges has no virtual columns.
+fn extract_columns<'a>(
+    column_ranges: &[ColumnRange],
+    columns: Columns<'a>,
+    virtual_columns: &[&'a [u8]],
+) -> Vec<&'a [u8]> {
+    extract_indices(column_ranges, columns.len())
+        .into_iter()
+        .map(|k| columns.get_extracted(k, virtual_columns))
+        .collect()
+}
+
+// Like extract_columns(), but the indices are stored in buffers and the columns are returned as an
+// iterator rather than collected, so that nothing is allocated.
+fn extract_columns_into<'a, 'b>(
+    column_ranges: &[ColumnRange],
+    columns: Columns<'a>,
+    virtual_columns: &'b [&'a [u8]],
+    buffers: &'b mut IndexBuffers,
+) -> impl Iterator<Item = &'a [u8]> + 'b {
+    extract_indices_into(column_ranges, columns.len(), buffers);
+    buffers
+        .results
+        .iter()
+        .map(move |&k| columns.get_extracted(k, virtual_columns))
+}
+
+// Extract the columns specified by column_ranges like extract_columns() and join them with
+// separator into output, replacing its contents, except that a column that immediately follows the
+// previous column in the line and comes from the same range (e.g. 2:5, but not 2:3 4) is joined to
+// it with the original text between them, which keeps the delimiters and alignment of the input.  columns.text must be the
+// line, as it is unless the line was parsed as CSV.
+fn join_preserving_delimiters_into(
+    column_ranges: &[ColumnRange],
+    columns: Columns,
+    virtual_columns: &[&[u8]],
+    separator: &[u8],
+    buffers: &mut IndexBuffers,
+    output: &mut Vec<u8>,
+) {
+    extract_indices_into(column_ranges, columns.len(), buffers);
+    output.clear();
+    let mut previous: Option<(usize, usize)> = None;
+    for (&k, &source) in buffers.results.iter().zip(&buffers.sources) {
+        let gap = previous
+            .filter(|&(previous, previous_source)| {
+                previous > 0 && previous + 1 == k && k < columns.len() && previous_source == source
+            })
+            .map(|(previous, _)| {
+                &columns.text[columns.spans[previous].end..columns.spans[k].start]
+            });
+        match (previous, gap) {
+            (None, _) => {}
+            (Some(_), Some(gap)) => output.extend_from_slice(gap),
+            (Some(_), None) => output.extend_from_slice(separator),
+        }
+        output.extend_from_slice(columns.get_extracted(k, virtual_columns));
+        previous = Some((k, source));
+    }
+}
+
+// Extract the columns specified by column_ranges like extract_columns() and join them with
+// separator into output, replacing its contents.  This is used for every line in the common case,
+// so it reuses buffers and output rather than allocating.
+fn join_columns_into(
+    column_ranges: &[ColumnRange],
+    columns: Columns,
+    virtual_columns: &[&[u8]],
+    separator: &[u8],
+    buffers: &mut IndexBuffers,
+    output: &mut Vec<u8>,
+) {
+    extract_indices_into(column_ranges, columns.len(), buffers);
+    output.clear();
+    push_columns(
+        &buffers.results,
+        columns,
+        virtual_columns,
+        separator,
+        output,
+    );
+}
+
+// Appends the columns with the given indices from extract_indices() to output, joined with
+// separator.
+fn push_columns(
+    indices: &[usize],
+    columns: Columns,
+    virtual_columns: &[&[u8]],
+    separator: &[u8],
+    output: &mut Vec<u8>,
+) {
+    for (i, &k) in indices.iter().enumerate() {
+        if i > 0 {
+            output.extend_from_slice(separator);
+        }
+        output.extend_from_slice(columns.get_extracted(k, virtual_columns));
+    }
+}
+
+// Replaces the contents of output with number in decimal, so that virtual columns don't allocate.
+fn format_number_into(number: usize, output: &mut Vec<u8>) {
+    output.clear();
+    write!(output, "{number}").unwrap();
+}
+
+// Split line into columns using delimiter, appending the position of the whole line followed by the
+// positions of the columns to spans, which is normally empty.  Empty columns are discarded unless
+// keep_empty is true.  An empty line has no columns.
+fn split_line(delimiter: &Delimiter, line: &[u8], keep_empty: bool, spans: &mut Vec<Range<usize>>) {
+    spans.push(0..line.len());
+    if !line.is_empty() {
+        spans.extend(
+            delimiter
+                .split_ranges(line)
+                .filter(|span| keep_empty || !span.is_empty()),
+        );
+    }
+}
+
+// Match line against pattern, appending the position of the whole line followed by the position of
+// each capture group to spans, and returning false if the line doesn't match.  Groups that don't
+// participate in the match are empty columns, so every matching line has the same number of
+// columns.  locations is reused for every line, and must come from pattern.capture_locations().
+fn capture_columns(
+    pattern: &regex::bytes::Regex,
+    locations: &mut regex::bytes::CaptureLocations,
+    line: &[u8],
+    spans: &mut Vec<Range<usize>>,
+) -> bool {
+    if pattern.captures_read(locations, line).is_none() {
+        return false;
+    }
+    spans.push(0..line.len());
+    spans.extend(
+        (1..locations.len()).map(|i| locations.get(i).map_or(0..0, |(start, end)| start..end)),
+    );
+    true
+}
+
+// Returns the names of the capture groups in pattern, in the same layout as a header line so that
+// they can be passed to resolve_column_names().  Unnamed groups have an empty name, which never
+// matches a column name.
+fn capture_group_names(pattern: &regex::bytes::Regex) -> Vec<&[u8]> {
+    pattern
+        .capture_names()
+        .map(|name| name.unwrap_or("").as_bytes())
+        .collect()
+}
+
+// Handles an error writing to stdout by exiting, because nothing more can be output.  A broken pipe,
+// e.g. from colx 1 | head, isn't reported, and the exit status is the one a shell reports for a
+// process killed by SIGPIPE, just like other tools.
+fn exit_on_write_error(e: std::io::Error) -> ! {
+    if e.kind() == std::io::ErrorKind::BrokenPipe {
+        process::exit(128 + 13);
     }
-    results
+    eprintln_wrapper(format!("colx: write error: {}", describe_error(&e)));
+    process::exit(1);
 }
 
-// A thin wrapper around println!.  This allows me to do dependency injection during tests to
-// validate that the correct data would have been output.
-fn println_wrapper(print_me: String) {
-    println!("{}", print_me);
+// Returns the terminator written after each output record: --output-record-separator if given,
+// otherwise NUL with -z or newline.
+fn output_record_separator(flags: &Flags) -> Vec<u8> {
+    match &flags.output_record_separator {
+        Some(separator) => separator.as_bytes().to_vec(),
+        None if flags.null_data => b"\0".to_vec(),
+        None => b"\n".to_vec(),
+    }
 }
 
 // A thin wrapper around eprintln!.  This allows me to do dependency injection during tests to
@@ -217,48 +1318,416 @@ fn eprintln_wrapper(print_me: String) {
 // validate that the correct data is generated.  I'm using dependency injection rather than
 // accumulating a giant array so that processing large files doesn't require memory proportional to
 // the file sizes.
-fn realmain<OH: FnMut(String), EH: FnMut(String)>(
+fn realmain<OH: FnMut(&[u8]), EH: FnMut(String)>(
     flags: Flags,
     mut output_handler: OH,
     mut error_handler: EH,
 ) -> i32 {
-    let delimiter = Regex::new(flags.delimiter.as_str());
+    let delimiter = if flags.fixed_delimiter {
+        Delimiter::fixed(&flags.delimiter)
+    } else {
+        Delimiter::regex(&flags.delimiter)
+            .map_err(|error_message| format!("Failed compiling delimiter regex: {error_message}"))
+    };
     let delimiter = match delimiter {
-        Ok(re) => re,
+        Ok(delimiter) => delimiter,
         Err(error_message) => {
-            error_handler(format!("Failed compiling delimiter regex: {error_message}"));
+            error_handler(error_message);
+            return 1;
+        }
+    };
+
+    let record_separator = if flags.null_data {
+        RecordSeparator::Byte(b'\0')
+    } else {
+        match RecordSeparator::parse(flags.record_separator.as_deref().unwrap_or("\n")) {
+            Ok(record_separator) => record_separator,
+            Err(error_message) => {
+                error_handler(format!(
+                    "Failed compiling --record-separator regex: {error_message}"
+                ));
+                return 1;
+            }
+        }
+    };
+
+    let pattern = match flags.pattern.as_deref().map(regex::bytes::Regex::new) {
+        None => None,
+        Some(Ok(pattern)) => Some(pattern),
+        Some(Err(error_message)) => {
+            error_handler(format!("Failed compiling --pattern regex: {error_message}"));
             return 1;
         }
     };
+    let to_usize = |numbers: &Vec<u64>| -> Vec<usize> {
+        numbers.iter().map(|&n| n.try_into().unwrap()).collect()
+    };
+    // With --aligned this is set when the first lines of each input are read.
+    let mut fixed_width = match (&flags.widths, &flags.cut_points) {
+        (Some(widths), _) => Some(FixedWidth::from_widths(
+            &to_usize(widths),
+            flags.characters,
+            flags.trim,
+        )),
+        (None, Some(cut_points)) => {
+            match FixedWidth::from_cut_points(&to_usize(cut_points), flags.characters, flags.trim) {
+                Ok(fixed_width) => Some(fixed_width),
+                Err(error_message) => {
+                    error_handler(error_message);
+                    return 1;
+                }
+            }
+        }
+        (None, None) => None,
+    };
+    // Named capture groups in --pattern can be selected by name, just like header columns.
+    let allow_names = flags.header || pattern.is_some();
 
-    let (column_ranges, filenames) = separate_args(flags.columns_then_files);
-    if column_ranges.is_empty() {
+    let mut template = None;
+    let (mut column_ranges, mut named_ranges, filenames) = if let Some(format) = &flags.format {
+        let parsed = Template::parse(format)
+            .map_err(|error_message| format!("Invalid --format template: {error_message}"))
+            .and_then(|(parsed_template, placeholders)| {
+                template = Some(parsed_template);
+                parse_placeholders(&placeholders, allow_names)
+            });
+        match parsed {
+            Ok((column_ranges, named_ranges)) => {
+                (column_ranges, named_ranges, flags.columns_then_files)
+            }
+            Err(error_message) => {
+                error_handler(error_message);
+                return 1;
+            }
+        }
+    } else if let Some(list) = &flags.columns {
+        match parse_column_list(list, allow_names) {
+            Ok((column_ranges, named_ranges)) => {
+                (column_ranges, named_ranges, flags.columns_then_files)
+            }
+            Err(error_message) => {
+                error_handler(error_message);
+                return 1;
+            }
+        }
+    } else {
+        let separated = if allow_names {
+            separate_named_args(flags.columns_then_files)
+                .map(|(named_ranges, filenames)| (vec![], named_ranges, filenames))
+        } else {
+            separate_args(flags.columns_then_files)
+                .map(|(column_ranges, filenames)| (column_ranges, vec![], filenames))
+        };
+        match separated {
+            Ok(separated) => separated,
+            Err(error_message) => {
+                error_handler(error_message);
+                return 1;
+            }
+        }
+    };
+    if column_ranges.is_empty() && named_ranges.is_empty() {
         error_handler(String::from(
             "At least one column or column range must be provided.",
         ));
         return 1;
     }
-    let input = match MultipleFileReader::new(filenames) {
-        Ok(input) => input,
-        Err(e) => {
-            error_handler(format!("{e}"));
-            return 1;
+    let mut predicates = vec![];
+    for predicate in flags.predicates.iter() {
+        match Predicate::parse(predicate, allow_names) {
+            Ok(parsed) => predicates.push(parsed),
+            Err(error_message) => {
+                error_handler(format!(
+                    "Invalid --where predicate \"{predicate}\": {error_message}"
+                ));
+                return 1;
+            }
         }
-    };
-
-    for line in BufReader::new(input).lines() {
-        let line = line.unwrap();
-        let all_columns: Vec<&str> = std::iter::once(line.as_str())
-            .chain(delimiter.split(&line).filter(|col| !col.is_empty()))
-            .collect();
-        let wanted_columns = extract_columns(&column_ranges, &all_columns);
-        output_handler(wanted_columns.join(&flags.separator));
     }
-    0
-}
+    if flags.complement {
+        column_ranges
+            .iter_mut()
+            .for_each(|range| range.exclude = true);
+        named_ranges
+            .iter_mut()
+            .for_each(|range| range.exclude = true);
+    }
+    if let Some(pattern) = &pattern {
+        let names = capture_group_names(pattern);
+        let resolved = resolve_column_names(&named_ranges, &names).and_then(|ranges| {
+            for predicate in predicates.iter_mut() {
+                predicate.resolve_column_names(&names)?;
+            }
+            Ok(ranges)
+        });
+        column_ranges = match resolved {
+            Ok(column_ranges) => column_ranges,
+            Err(error_message) => {
+                error_handler(format!(
+                    "{error_message}  With --pattern the header is the capture group names."
+                ));
+                return 1;
+            }
+        };
+    }
+    // --strict stops before any output if a file can't be opened, just like older versions.
+    let check_files = flags.check_files || flags.strict;
+    let mut input = match MultipleFileReader::new(filenames, check_files) {
+        Ok(input) => input,
+        Err(e) => {
+            error_handler(format!("colx: {e}"));
+            return 1;
+        }
+    };
+    let mut input_failed = false;
+    let virtual_columns_used = column_ranges
+        .iter()
+        .any(|column_range| column_range.virtual_column.is_some())
+        || named_ranges
+            .iter()
+            .any(|named_range| virtual_column_range(named_range).is_some())
+        || predicates.iter().any(Predicate::uses_virtual_columns);
+
+    let mut aligner = flags.align.map(|alignments| {
+        let buffer_lines = flags.align_buffer.map(|lines| lines.try_into().unwrap());
+        output::Aligner::new(alignments, flags.separator.as_bytes(), buffer_lines)
+    });
+    let mut need_header = flags.header;
+    let mut header_names: Option<Vec<Vec<u8>>> = None;
+    let mut record_number: usize = 0;
+    // Buffers that are reused for every line, so that in the common case of splitting lines and
+    // outputting columns nothing is allocated per line.
+    let mut line = vec![];
+    let mut csv_text = vec![];
+    let mut column_spans = vec![];
+    let mut capture_locations = pattern.as_ref().map(regex::bytes::Regex::capture_locations);
+    let mut line_number_text = vec![];
+    let mut record_number_text = vec![];
+    let mut index_buffers = IndexBuffers::default();
+    let mut output = vec![];
+    // Each input is read separately so that lines don't span inputs and so that the virtual
+    // columns can report where each line came from.
+    while let Some(file) = input.next_input() {
+        let filename = file.name.clone();
+        let lines = record_separator.records(BufReader::new(file), flags.lossy);
+        let mut records: Box<dyn ReadRecord> = if flags.csv {
+            Box::new(IterRecords(csv::CsvRecords::new(
+                lines.keep_carriage_returns(),
+            )))
+        } else {
+            Box::new(lines)
+        };
+        if flags.aligned {
+            // Read ahead to find the column positions.  Each input has its own header, e.g. from
+            // separate runs of ps, so the positions are found for each input.  The lines that were
+            // read ahead are then processed as usual, and so are any errors.
+            let sample_size = 1 + usize::try_from(flags.aligned_lines.unwrap_or(0)).unwrap();
+            let read_ahead = ReadAhead::new(records, sample_size);
+            let sample = read_ahead.records();
+            fixed_width =
+                (!sample.is_empty()).then(|| FixedWidth::from_aligned(&sample, flags.characters));
+            records = Box::new(read_ahead);
+        }
+        let mut line_number: usize = 0;
+        loop {
+            match records.read_record(&mut line) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    error_handler(format!("colx: {e}"));
+                    if flags.strict {
+                        return 1;
+                    }
+                    // Skip the rest of the failed input and carry on with the remaining inputs.
+                    input_failed = true;
+                    break;
+                }
+            }
+            line_number += 1;
+            record_number += 1;
+            'line: {
+                column_spans.clear();
+                let text = if flags.csv {
+                    csv_text.clear();
+                    csv_text.extend_from_slice(&line);
+                    column_spans.push(0..line.len());
+                    csv::parse_record_into(&line, &mut csv_text, &mut column_spans);
+                    &csv_text
+                } else {
+                    if let Some(pattern) = &pattern {
+                        let locations = capture_locations.as_mut().unwrap();
+                        if !capture_columns(pattern, locations, &line, &mut column_spans) {
+                            break 'line;
+                        }
+                    } else if let Some(fixed_width) = &fixed_width {
+                        fixed_width.split_ranges(&line, &mut column_spans);
+                    } else {
+                        split_line(&delimiter, &line, flags.keep_empty, &mut column_spans);
+                    }
+                    &line
+                };
+                let columns = Columns::new(text, &column_spans);
+                let is_header = need_header;
+                if need_header {
+                    let header: Vec<&[u8]> = columns.iter().collect();
+                    let resolved =
+                        resolve_column_names(&named_ranges, &header).and_then(|ranges| {
+                            for predicate in predicates.iter_mut() {
+                                predicate.resolve_column_names(&header)?;
+                            }
+                            Ok(ranges)
+                        });
+                    column_ranges = match resolved {
+                        Ok(column_ranges) => column_ranges,
+                        Err(error_message) => {
+                            error_handler(error_message);
+                            return 1;
+                        }
+                    };
+                    need_header = false;
+                    if flags.output_format == OutputFormat::Json {
+                        header_names = Some(header.iter().map(|name| name.to_vec()).collect());
+                        break 'line;
+                    }
+                }
+                let virtual_columns: [&[u8]; 3] = if !virtual_columns_used {
+                    [b""; 3]
+                } else if is_header {
+                    // Label the virtual columns in the header line.
+                    VirtualColumn::ALL.map(|column| column.name().as_bytes())
+                } else {
+                    format_number_into(line_number, &mut line_number_text);
+                    format_number_into(record_number, &mut record_number_text);
+                    VirtualColumn::ALL.map(|column| match column {
+                        VirtualColumn::Filename => filename.as_bytes(),
+                        VirtualColumn::LineNumber => line_number_text.as_slice(),
+                        VirtualColumn::RecordNumber => record_number_text.as_slice(),
+                    })
+                };
+                if !is_header
+                    && !predicates
+                        .iter()
+                        .all(|predicate| predicate.matches(columns, &virtual_columns))
+                {
+                    break 'line;
+                }
+                if let Some(template) = &template {
+                    template.render_into(
+                        &column_ranges,
+                        columns,
+                        &virtual_columns,
+                        flags.separator.as_bytes(),
+                        &mut index_buffers,
+                        &mut output,
+                    );
+                    output_handler(&output);
+                    break 'line;
+                }
+                if let Some(aligner) = aligner.as_mut() {
+                    let columns = extract_columns(&column_ranges, columns, &virtual_columns)
+                        .iter()
+                        .map(|column| column.to_vec())
+                        .collect();
+                    aligner.push(columns, &mut output_handler);
+                    break 'line;
+                }
+                let separator = flags.separator.as_bytes();
+                match flags.output_format {
+                    OutputFormat::Plain if flags.preserve_delimiters => {
+                        join_preserving_delimiters_into(
+                            &column_ranges,
+                            columns,
+                            &virtual_columns,
+                            separator,
+                            &mut index_buffers,
+                            &mut output,
+                        )
+                    }
+                    OutputFormat::Plain => join_columns_into(
+                        &column_ranges,
+                        columns,
+                        &virtual_columns,
+                        separator,
+                        &mut index_buffers,
+                        &mut output,
+                    ),
+                    OutputFormat::Csv => output::format_csv_into(
+                        extract_columns_into(
+                            &column_ranges,
+                            columns,
+                            &virtual_columns,
+                            &mut index_buffers,
+                        ),
+                        &mut output,
+                    ),
+                    OutputFormat::Tsv => output::format_tsv_into(
+                        extract_columns_into(
+                            &column_ranges,
+                            columns,
+                            &virtual_columns,
+                            &mut index_buffers,
+                        ),
+                        &mut output,
+                    ),
+                    OutputFormat::Json => match &header_names {
+                        None => output::format_json_array_into(
+                            extract_columns_into(
+                                &column_ranges,
+                                columns,
+                                &virtual_columns,
+                                &mut index_buffers,
+                            ),
+                            &mut output,
+                        ),
+                        Some(header_names) => {
+                            // Lines can have more columns than the header, so fall back to column
+                            // numbers.  Virtual columns follow the real columns and use their names.
+                            extract_indices_into(&column_ranges, columns.len(), &mut index_buffers);
+                            let keys_and_columns = index_buffers.results.iter().map(|&k| {
+                                let key = match k.checked_sub(columns.len()) {
+                                    Some(v) => {
+                                        JsonKey::Name(VirtualColumn::ALL[v].name().as_bytes())
+                                    }
+                                    None => header_names
+                                        .get(k)
+                                        .map_or(JsonKey::Number(k), |name| JsonKey::Name(name)),
+                                };
+                                (key, columns.get_extracted(k, &virtual_columns))
+                            });
+                            output::format_json_object_into(keys_and_columns, &mut output)
+                        }
+                    },
+                }
+                output_handler(&output);
+            }
+        }
+    }
+    if let Some(aligner) = aligner.as_mut() {
+        aligner.flush(&mut output_handler);
+    }
+    // Like grep, exit with 2 rather than 1 when some inputs couldn't be read, so that callers can
+    // tell partial output apart from other errors.
+    if input_failed { 2 } else { 0 }
+}
 
 fn main() {
-    process::exit(realmain(Flags::parse(), println_wrapper, eprintln_wrapper));
+    let flags = Flags::parse();
+    let stdout = std::io::stdout();
+    // Flush every line when a person is watching, otherwise buffer output for speed.
+    let line_buffered = stdout.is_terminal();
+    let mut writer = RecordWriter::new(
+        stdout.lock(),
+        output_record_separator(&flags),
+        line_buffered,
+    );
+    let output_handler = |record: &[u8]| {
+        writer
+            .write_record(record)
+            .unwrap_or_else(|e| exit_on_write_error(e))
+    };
+    let status = realmain(flags, output_handler, eprintln_wrapper);
+    writer.flush().unwrap_or_else(|e| exit_on_write_error(e));
+    process::exit(status);
 }
 
 #[cfg(test)]
@@ -287,6 +1756,128 @@ mod clap_test {
         ]);
         assert_eq!("asdf", flags.separator);
         assert_eq!("qwerty", flags.delimiter);
+        assert!(!flags.header);
+
+        let flags = Flags::parse_from(vec!["argv0", "--header", "NAME"]);
+        assert!(flags.header);
+        assert!(!flags.complement);
+
+        let flags = Flags::parse_from(vec!["argv0", "--complement", "1"]);
+        assert!(flags.complement);
+
+        let flags = Flags::parse_from(vec!["argv0", "-c", "-1,2", "3"]);
+        assert_eq!(Some(String::from("-1,2")), flags.columns);
+        assert_eq!(vec!["3"], flags.columns_then_files);
+
+        let flags = Flags::parse_from(vec!["argv0", "--csv", "1"]);
+        assert!(flags.csv);
+        assert!(Flags::try_parse_from(vec!["argv0", "--csv", "-d", ",", "1"]).is_err());
+        assert_eq!(OutputFormat::Plain, flags.output_format);
+
+        let flags = Flags::parse_from(vec!["argv0", "--output-format", "tsv", "1"]);
+        assert_eq!(OutputFormat::Tsv, flags.output_format);
+        assert!(!flags.lossy);
+        assert!(!flags.keep_empty);
+        assert_eq!(Vec::<String>::new(), flags.predicates);
+
+        let flags = Flags::parse_from(vec!["argv0", "--where", "1==a", "--where", "2>1", "1"]);
+        assert_eq!(vec!["1==a", "2>1"], flags.predicates);
+        assert_eq!(None, flags.align);
+
+        let flags = Flags::parse_from(vec!["argv0", "--align", "1"]);
+        assert_eq!(Some(vec![Alignment::Left]), flags.align);
+        assert_eq!(vec!["1"], flags.columns_then_files);
+        assert_eq!(None, flags.align_buffer);
+
+        let flags = Flags::parse_from(vec!["argv0", "--align=r,n", "--align-buffer", "9", "1"]);
+        assert_eq!(
+            Some(vec![Alignment::Right, Alignment::Numeric]),
+            flags.align
+        );
+        assert_eq!(Some(9), flags.align_buffer);
+        assert!(Flags::try_parse_from(vec!["argv0", "--align-buffer", "9", "1"]).is_err());
+        assert!(Flags::try_parse_from(vec!["argv0", "--align", "--align-buffer", "0"]).is_err());
+        assert_eq!(None, flags.format);
+
+        let flags = Flags::parse_from(vec!["argv0", "--format", "{1}", "file"]);
+        assert_eq!(Some(String::from("{1}")), flags.format);
+        assert!(Flags::try_parse_from(vec!["argv0", "--format", "{1}", "--align"]).is_err());
+        assert!(!flags.check_files);
+
+        let flags = Flags::parse_from(vec!["argv0", "--check-files", "1"]);
+        assert!(flags.check_files);
+        assert!(!flags.strict);
+
+        let flags = Flags::parse_from(vec!["argv0", "--strict", "1"]);
+        assert!(flags.strict);
+        assert!(!flags.fixed_delimiter);
+
+        let flags = Flags::parse_from(vec!["argv0", "-F", "-d", "|", "1"]);
+        assert!(flags.fixed_delimiter);
+        assert!(Flags::try_parse_from(vec!["argv0", "-F", "1"]).is_err());
+        assert_eq!(None, flags.pattern);
+
+        let flags = Flags::parse_from(vec!["argv0", "--pattern", "(a)", "1"]);
+        assert_eq!(Some(String::from("(a)")), flags.pattern);
+        assert!(Flags::try_parse_from(vec!["argv0", "--pattern", "(a)", "--csv", "1"]).is_err());
+        assert!(Flags::try_parse_from(vec!["argv0", "--pattern", "(a)", "--header", "1"]).is_err());
+        assert_eq!(None, flags.widths);
+        assert_eq!(None, flags.cut_points);
+
+        let flags = Flags::parse_from(vec!["argv0", "--widths", "8,12", "--trim", "1"]);
+        assert_eq!(Some(vec![8, 12]), flags.widths);
+        assert!(flags.trim);
+        assert!(!flags.characters);
+
+        let flags = Flags::parse_from(vec!["argv0", "--cut-points", "3", "--characters", "1"]);
+        assert_eq!(Some(vec![3]), flags.cut_points);
+        assert!(flags.characters);
+        assert!(!flags.trim);
+        assert!(!flags.aligned);
+        assert_eq!(None, flags.aligned_lines);
+        assert!(Flags::try_parse_from(vec!["argv0", "--trim", "1"]).is_err());
+        assert!(Flags::try_parse_from(vec!["argv0", "--widths", "0", "1"]).is_err());
+        assert!(
+            Flags::try_parse_from(vec!["argv0", "--widths", "1", "--cut-points", "1", "1"])
+                .is_err()
+        );
+
+        let flags = Flags::parse_from(vec!["argv0", "--aligned", "--aligned-lines", "5", "1"]);
+        assert!(flags.aligned);
+        assert_eq!(Some(5), flags.aligned_lines);
+        assert!(Flags::try_parse_from(vec!["argv0", "--aligned-lines", "5", "1"]).is_err());
+        assert!(Flags::try_parse_from(vec!["argv0", "--aligned", "--widths", "1", "1"]).is_err());
+        assert!(!flags.null_data);
+        assert_eq!(None, flags.record_separator);
+        assert_eq!(None, flags.output_record_separator);
+
+        let flags = Flags::parse_from(vec!["argv0", "-z", "--output-record-separator", ";", "1"]);
+        assert!(flags.null_data);
+        assert_eq!(Some(String::from(";")), flags.output_record_separator);
+        assert!(
+            Flags::try_parse_from(vec!["argv0", "-z", "--record-separator", ";", "1"]).is_err()
+        );
+
+        let flags = Flags::parse_from(vec!["argv0", "--record-separator", "", "1"]);
+        assert_eq!(Some(String::new()), flags.record_separator);
+        assert!(Flags::try_parse_from(vec!["argv0", "--record-separator", ";", "--csv"]).is_err());
+        assert!(!flags.preserve_delimiters);
+
+        let flags = Flags::parse_from(vec!["argv0", "--preserve-delimiters", "1"]);
+        assert!(flags.preserve_delimiters);
+        assert!(
+            Flags::try_parse_from(vec!["argv0", "--preserve-delimiters", "--align", "1"]).is_err()
+        );
+        assert!(
+            Flags::try_parse_from(vec![
+                "argv0",
+                "--preserve-delimiters",
+                "--pattern",
+                "x=(\\d)",
+                "1"
+            ])
+            .is_err()
+        );
     }
 }
 
@@ -313,7 +1904,7 @@ mod multiple_file_reader {
             call_count += 1;
             std::io::stdin()
         };
-        MultipleFileReader::new_with_opener(vec![String::from("-")], wrapper).unwrap();
+        MultipleFileReader::new_with_opener(vec![String::from("-")], false, wrapper).unwrap();
         assert_eq!(1, call_count);
     }
 
@@ -324,7 +1915,7 @@ mod multiple_file_reader {
             call_count += 1;
             std::io::stdin()
         };
-        MultipleFileReader::new_with_opener(vec![], wrapper).unwrap();
+        MultipleFileReader::new_with_opener(vec![], false, wrapper).unwrap();
         assert_eq!(1, call_count);
     }
 
@@ -344,6 +1935,7 @@ mod multiple_file_reader {
                 String::from("-"),
                 String::from("testdata/file2"),
             ],
+            false,
             wrapper,
         )
         .unwrap();
@@ -353,7 +1945,7 @@ mod multiple_file_reader {
     #[test]
     fn one_file() {
         let multi_file_reader =
-            MultipleFileReader::new(vec![String::from("testdata/file1")]).unwrap();
+            MultipleFileReader::new(vec![String::from("testdata/file1")], false).unwrap();
         let lines: Vec<String> = BufReader::new(multi_file_reader)
             .lines()
             .map(|l| l.unwrap())
@@ -373,7 +1965,7 @@ mod multiple_file_reader {
             String::from("testdata/file2"),
             String::from("testdata/file3"),
         ];
-        let multi_file_reader = MultipleFileReader::new(filenames).unwrap();
+        let multi_file_reader = MultipleFileReader::new(filenames, false).unwrap();
         let lines: Vec<String> = BufReader::new(multi_file_reader)
             .lines()
             .map(|l| l.unwrap())
@@ -402,17 +1994,57 @@ mod multiple_file_reader {
             String::from("testdata/file_does_not_exist"),
             String::from("testdata/file3"),
         ];
-        let multi_file_reader = MultipleFileReader::new(filenames);
+        let mut multi_file_reader = MultipleFileReader::new(filenames, false).unwrap();
+        let mut buffer = vec![];
+        let error = multi_file_reader.read_to_end(&mut buffer).unwrap_err();
+        assert_eq!(std::io::ErrorKind::NotFound, error.kind());
+        assert_eq!(
+            "testdata/file_does_not_exist: No such file or directory",
+            error.to_string()
+        );
+        // The file before the missing file was read.
+        assert_eq!(
+            b"This is file 1.\n\nIt is not very interesting.\n",
+            &buffer[..]
+        );
+        // Errors are returned without advancing to the next input.
+        assert!(multi_file_reader.read(&mut [0; 10]).is_err());
+    }
+
+    #[test]
+    fn open_fails_with_check_files() {
+        let filenames = vec![
+            String::from("testdata/file1"),
+            String::from("testdata/file_does_not_exist"),
+            String::from("testdata/file3"),
+        ];
+        let multi_file_reader = MultipleFileReader::new(filenames, true);
         assert!(multi_file_reader.is_err());
     }
 
+    #[test]
+    fn files_are_opened_lazily() {
+        let multi_file_reader =
+            MultipleFileReader::new(vec![String::from("testdata/file1")], false).unwrap();
+        assert!(matches!(
+            multi_file_reader.inputs.front(),
+            Some(Input {
+                state: InputState::Unopened,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn read_fails() {
         // We construct a filehandle that errors followed by a valid filehandle.
         // Reads should consistently fail rather than moving on to the valid filehandle.
-        let filehandles: Vec<Box<dyn Read>> = vec![
-            Box::new(ReadAlwaysFails {}),
-            Box::new(File::open("testdata/file1").expect("open(testdata/file1) failed?")),
+        let filehandles: Vec<(String, Box<dyn Read>)> = vec![
+            (String::from("fails"), Box::new(ReadAlwaysFails {})),
+            (
+                String::from("testdata/file1"),
+                Box::new(File::open("testdata/file1").expect("open(testdata/file1) failed?")),
+            ),
         ];
         let mut multi_file_reader = MultipleFileReader::new_from_filehandles(filehandles);
         let mut buffer = [0; 10];
@@ -421,6 +2053,45 @@ mod multiple_file_reader {
         assert!(multi_file_reader.read(&mut buffer).is_err());
     }
 
+    #[test]
+    fn next_input() {
+        let filenames = vec![
+            String::from("testdata/file1"),
+            String::from("testdata/file_does_not_exist"),
+        ];
+        let mut multi_file_reader = MultipleFileReader::new(filenames, false).unwrap();
+        let mut input = multi_file_reader.next_input().unwrap();
+        assert_eq!("testdata/file1", input.name);
+        let mut contents = String::new();
+        input.read_to_string(&mut contents).unwrap();
+        assert_eq!("This is file 1.\n\nIt is not very interesting.\n", contents);
+        assert_eq!(3, input.lines);
+        // Inputs are returned even if they can't be opened, because they are opened lazily.
+        let input = multi_file_reader.next_input().unwrap();
+        assert_eq!("testdata/file_does_not_exist", input.name);
+        assert!(multi_file_reader.next_input().is_none());
+    }
+
+    #[test]
+    fn read_error_includes_name_and_line() {
+        let filehandles: Vec<(String, Box<dyn Read>)> = vec![
+            (
+                String::from("testdata/file1"),
+                Box::new(File::open("testdata/file1").expect("open(testdata/file1) failed?")),
+            ),
+            (
+                String::from("broken"),
+                Box::new(std::io::Cursor::new(b"a\nb\nc").chain(ReadAlwaysFails {})),
+            ),
+        ];
+        let mut multi_file_reader = MultipleFileReader::new_from_filehandles(filehandles);
+        let mut buffer = vec![];
+        let error = multi_file_reader.read_to_end(&mut buffer).unwrap_err();
+        // Line numbers restart for each input.
+        assert_eq!("broken:3: oh no!", error.to_string());
+        assert_eq!(std::io::ErrorKind::Other, error.kind());
+    }
+
     #[test]
     fn empty_reader_returns_eof() {
         let mut reader = MultipleFileReader::new_from_filehandles(vec![]);
@@ -430,12 +2101,101 @@ mod multiple_file_reader {
 }
 
 #[cfg(test)]
-mod println_wrapper {
+mod split_line {
     use super::*;
 
+    fn split(delimiter: &str, line: &str, keep_empty: bool) -> Vec<String> {
+        let delimiter = Delimiter::regex(delimiter).unwrap();
+        let mut spans = vec![];
+        split_line(&delimiter, line.as_bytes(), keep_empty, &mut spans);
+        Columns::new(line.as_bytes(), &spans)
+            .iter()
+            .map(|column| String::from_utf8(column.to_vec()).unwrap())
+            .collect()
+    }
+
     #[test]
-    fn simple_test() {
-        println_wrapper(String::from("printed by println_wrapper test."));
+    fn discard_empty() {
+        assert_eq!(vec!["  a  b ", "a", "b"], split("\\s+", "  a  b ", false));
+        assert_eq!(vec![":a::c:", "a", "c"], split(":", ":a::c:", false));
+    }
+
+    #[test]
+    fn keep_empty() {
+        assert_eq!(
+            vec!["  a  b ", "", "a", "b", ""],
+            split("\\s+", "  a  b ", true)
+        );
+        assert_eq!(
+            vec![":a::c:", "", "a", "", "c", ""],
+            split(":", ":a::c:", true)
+        );
+    }
+
+    #[test]
+    fn empty_line() {
+        assert_eq!(vec![""], split(":", "", false));
+        assert_eq!(vec![""], split(":", "", true));
+    }
+}
+
+#[cfg(test)]
+mod capture_columns {
+    use super::*;
+
+    fn capture(pattern: &str, line: &str) -> Option<Vec<String>> {
+        let pattern = regex::bytes::Regex::new(pattern).unwrap();
+        let mut locations = pattern.capture_locations();
+        let mut spans = vec![];
+        capture_columns(&pattern, &mut locations, line.as_bytes(), &mut spans).then(|| {
+            Columns::new(line.as_bytes(), &spans)
+                .iter()
+                .map(|column| String::from_utf8(column.to_vec()).unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(
+            vec!["x=1 y=2", "x", "1", "2"],
+            capture(r"(\w)=(\d) y=(?<y>\d)", "x=1 y=2").unwrap()
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        assert_eq!(None, capture(r"(\d+)", "abc"));
+    }
+
+    #[test]
+    fn optional_groups_are_empty() {
+        assert_eq!(
+            vec!["ac", "a", "", "c"],
+            capture(r"(a)(b)?(c)", "ac").unwrap()
+        );
+    }
+
+    #[test]
+    fn names() {
+        let pattern = regex::bytes::Regex::new(r"(?<a>.)(.)(?<c>.)").unwrap();
+        let names: Vec<&[u8]> = vec![b"", b"a", b"", b"c"];
+        assert_eq!(names, capture_group_names(&pattern));
+    }
+}
+
+#[cfg(test)]
+mod output_record_separator {
+    use super::*;
+
+    #[test]
+    fn separators() {
+        let flags = Flags::parse_from(vec!["argv0", "1"]);
+        assert_eq!(b"\n".to_vec(), output_record_separator(&flags));
+        let flags = Flags::parse_from(vec!["argv0", "-z", "1"]);
+        assert_eq!(b"\0".to_vec(), output_record_separator(&flags));
+        let flags = Flags::parse_from(vec!["argv0", "-z", "--output-record-separator", ";", "1"]);
+        assert_eq!(b";".to_vec(), output_record_separator(&flags));
     }
 }
 
@@ -453,8 +2213,14 @@ mod eprintln_wrapper {
 mod realmain {
     use super::*;
 
-    fn panic_if_called(message: String) {
-        panic!("output_handler/error_handler should not have been called!  {message}");
+    fn panic_if_called<T: std::fmt::Debug>(message: T) {
+        panic!("output_handler/error_handler should not have been called!  {message:?}");
+    }
+
+    // output_handler takes a borrowed slice, which a generic function can't accept for every
+    // lifetime, so it needs its own function.
+    fn panic_if_output(output: &[u8]) {
+        panic_if_called(output);
     }
 
     #[test]
@@ -467,8 +2233,8 @@ mod realmain {
     fn expected_columns() {
         let expected = vec![String::from("This"), String::from(""), String::from("It")];
         let mut output_strings: Vec<String> = vec![];
-        let output_handler = |output_string: String| {
-            output_strings.push(output_string);
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
             Flags::parse_from(vec!["argv0", "1", "testdata/file1"]),
@@ -479,12 +2245,111 @@ mod realmain {
         assert_eq!(expected, output_strings);
     }
 
+    #[test]
+    fn columns_flag() {
+        let expected = vec![
+            String::from("is This"),
+            String::from(""),
+            String::from("is It"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "-c", "2,1", "testdata/file1"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn virtual_columns() {
+        let expected = vec![
+            "testdata/file1 1 1 This",
+            "testdata/file1 2 2",
+            "testdata/file1 3 3 It",
+            "testdata/file3 1 4 File",
+            "testdata/file3 2 5 deleted",
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "F",
+                "N",
+                "NR",
+                "1",
+                "testdata/file1",
+                "testdata/file3",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn virtual_columns_with_header() {
+        let expected = vec!["N NAME", "2 web-1", "3 db-1"];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "N",
+                "NAME",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn virtual_columns_json_keys() {
+        let expected = vec![
+            r#"{"F":"testdata/file_with_header","NAME":"web-1"}"#,
+            r#"{"F":"testdata/file_with_header","NAME":"db-1"}"#,
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "--output-format",
+                "json",
+                "F",
+                "NAME",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
     #[test]
     fn empty_columns() {
         let expected = vec![String::from("empty after")];
         let mut output_strings: Vec<String> = vec![];
-        let output_handler = |output_string: String| {
-            output_strings.push(output_string);
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
             Flags::parse_from(vec![
@@ -505,8 +2370,8 @@ mod realmain {
     fn change_delimiter() {
         let expected = vec![String::from("  empty  column  ")];
         let mut output_strings: Vec<String> = vec![];
-        let output_handler = |output_string: String| {
-            output_strings.push(output_string);
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
             Flags::parse_from(vec![
@@ -536,28 +2401,27 @@ mod realmain {
                 "1",
                 "testdata/file_with_empty_columns",
             ]),
-            panic_if_called,
+            panic_if_output,
             error_handler,
         );
         assert_eq!(1, status);
     }
 
     #[test]
-    fn change_separator() {
-        let expected = vec![String::from("emptyASDFafter")];
+    fn fixed_delimiter() {
+        let expected = vec!["b", "", "d"];
         let mut output_strings: Vec<String> = vec![];
-        let output_handler = |output_string: String| {
-            output_strings.push(output_string);
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
             Flags::parse_from(vec![
                 "argv0",
-                "--separator",
-                "ASDF",
-                "1",
-                "--",
-                "-1",
-                "testdata/file_with_empty_columns",
+                "-F",
+                "-d",
+                "[as",
+                "2",
+                "testdata/file_with_fixed_delimiter",
             ]),
             output_handler,
             panic_if_called,
@@ -567,138 +2431,1531 @@ mod realmain {
     }
 
     #[test]
-    fn no_columns() {
-        let error_handler = |message: String| {
-            assert_eq!(
-                "At least one column or column range must be provided.",
-                message
-            );
+    fn pattern() {
+        let expected = vec![
+            String::from("bob login 2026-10-01"),
+            String::from("alice logout 2026-10-01"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
-            Flags::parse_from(vec!["argv0", "testdata/file1"]),
+            Flags::parse_from(vec![
+                "argv0",
+                "--pattern",
+                r"^\[(\S+) (\S+)\] user=(?<user>\w+) action=(?<action>\w+)",
+                "user",
+                "4",
+                "1",
+                "testdata/file_with_log_lines",
+            ]),
+            output_handler,
             panic_if_called,
-            error_handler,
         );
-        assert_eq!(1, status);
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
     }
 
     #[test]
-    #[should_panic]
-    fn non_existent_file() {
-        realmain(
-            Flags::parse_from(vec!["argv0", "1", "testdata/file_does_not_exist"]),
-            panic_if_called,
+    fn pattern_with_where_and_format() {
+        let expected = vec![String::from("alice@12:05")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--pattern",
+                r"(?<time>\d+:\d+)\] user=(?<user>\w+) action=(?<action>\w+)",
+                "--where",
+                "action==logout",
+                "--format",
+                "{user}@{time}",
+                "testdata/file_with_log_lines",
+            ]),
+            output_handler,
             panic_if_called,
         );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
     }
 
     #[test]
-    fn open_fails() {
-        let mut error_handler_called = false;
-        let error_handler = |message: String| {
-            assert!(message.contains("No such file or directory"));
-            error_handler_called = true;
+    fn fixed_widths() {
+        let expected = vec![
+            String::from("New York,Jo Ann,42"),
+            String::from("Los Angeles,Bob,7"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
-            Flags::parse_from(vec!["argv0", "1", "testdata/does_not_exist"]),
+            Flags::parse_from(vec![
+                "argv0",
+                "--widths",
+                "8,12",
+                "--trim",
+                "--separator",
+                ",",
+                "2",
+                "1",
+                "3",
+                "testdata/file_with_fixed_widths",
+            ]),
+            output_handler,
             panic_if_called,
-            error_handler,
         );
-        assert_eq!(1, status);
-        assert!(error_handler_called);
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
     }
 
     #[test]
-    fn multiple_files() {
+    fn cut_points_without_trim() {
         let expected = vec![
-            String::from("This is file 1."),
-            String::from(""),
+            String::from("[New York    ]"),
+            String::from("[Los Angeles ]"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--cut-points",
+                "8,20",
+                "--format",
+                "[{2}]",
+                "testdata/file_with_fixed_widths",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn aligned() {
+        let expected = vec![
+            String::from("CONTAINER ID|CREATED"),
+            String::from("abc123def456|2 hours ago"),
+            String::from("0123456789ab|3 days ago"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--aligned",
+                "--aligned-lines",
+                "10",
+                "--header",
+                "--separator",
+                "|",
+                "CONTAINER ID",
+                "CREATED",
+                "testdata/file_with_aligned_columns",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn aligned_positions_for_each_input() {
+        let expected = vec!["IMAGE", "nginx", "redis", "IMAGE", "alpine"];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--aligned",
+                "--aligned-lines",
+                "10",
+                "2",
+                "testdata/file_with_aligned_columns",
+                "testdata/file_with_other_aligned_columns",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn aligned_header_only() {
+        // Without --aligned-lines "CONTAINER ID" is split into two columns.
+        let expected = vec![String::from("ID"), String::from("56"), String::from("ab")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--aligned",
+                "2",
+                "testdata/file_with_aligned_columns",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn null_data() {
+        let expected = vec![String::from("b"), String::from("d")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "-z", "2", "testdata/file_with_nul_records"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn paragraph_records() {
+        let expected = vec![String::from("web-1 Running"), String::from("db-1 Pending")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--record-separator",
+                "",
+                "2",
+                "4",
+                "testdata/file_with_paragraphs",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn bad_record_separator() {
+        let error_handler = |message: String| {
+            assert!(
+                message.contains("Failed compiling --record-separator regex: regex parse error")
+            );
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--record-separator",
+                "(ab",
+                "1",
+                "testdata/file_with_paragraphs",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+    }
+
+    #[test]
+    fn preserve_delimiters() {
+        let expected = vec![
+            String::from("root:0.0 /sbin/init"),
+            String::from("www:12.5 nginx:"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--preserve-delimiters",
+                "--separator",
+                ":",
+                "1",
+                "3:4",
+                "testdata/file_with_aligned_values",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn decreasing_cut_points() {
+        let mut error_messages = vec![];
+        let error_handler = |message: String| error_messages.push(message);
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--cut-points",
+                "8,4",
+                "1",
+                "testdata/file_with_fixed_widths",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert_eq!(
+            vec![String::from("--cut-points must be in increasing order.")],
+            error_messages
+        );
+    }
+
+    #[test]
+    fn pattern_missing_name() {
+        let mut error_messages = vec![];
+        let error_handler = |message: String| error_messages.push(message);
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--pattern",
+                r"user=(?<user>\w+)",
+                "host",
+                "testdata/file_with_log_lines",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert_eq!(
+            vec![String::from(
+                "Column name \"host\" not found in header.  With --pattern the header is the \
+                 capture group names."
+            )],
+            error_messages
+        );
+    }
+
+    #[test]
+    fn bad_pattern() {
+        let error_handler = |message: String| {
+            assert!(message.contains("Failed compiling --pattern regex: regex parse error"));
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--pattern",
+                "(",
+                "1",
+                "testdata/file_with_log_lines",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+    }
+
+    #[test]
+    fn empty_fixed_delimiter() {
+        let mut error_strings = vec![];
+        let error_handler = |message: String| error_strings.push(message);
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "-F", "-d", "", "1"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert_eq!(vec!["Fixed delimiter must not be empty."], error_strings);
+    }
+
+    #[test]
+    fn change_separator() {
+        let expected = vec![String::from("emptyASDFafter")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--separator",
+                "ASDF",
+                "1",
+                "--",
+                "-1",
+                "testdata/file_with_empty_columns",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn no_columns() {
+        let error_handler = |message: String| {
+            assert_eq!(
+                "At least one column or column range must be provided.",
+                message
+            );
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "testdata/file1"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+    }
+
+    #[test]
+    #[should_panic]
+    fn non_existent_file() {
+        realmain(
+            Flags::parse_from(vec!["argv0", "1", "testdata/file_does_not_exist"]),
+            panic_if_output,
+            panic_if_called,
+        );
+    }
+
+    #[test]
+    fn open_fails() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert!(message.contains("No such file or directory"));
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "1", "testdata/does_not_exist"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(2, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn open_fails_and_continues() {
+        let mut output_strings = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let mut error_strings = vec![];
+        let error_handler = |message: String| error_strings.push(message);
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "1",
+                "testdata/file1",
+                "testdata/does_not_exist",
+                "testdata/file3",
+            ]),
+            output_handler,
+            error_handler,
+        );
+        assert_eq!(2, status);
+        assert_eq!(vec!["This", "", "It", "File", "deleted"], output_strings);
+        assert_eq!(
+            vec!["colx: testdata/does_not_exist: No such file or directory"],
+            error_strings
+        );
+    }
+
+    #[test]
+    fn read_fails_and_continues() {
+        let mut output_strings = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let mut error_strings = vec![];
+        let error_handler = |message: String| error_strings.push(message);
+        // Opening a directory succeeds but reading from it fails.
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "1", "testdata", "testdata/file3"]),
+            output_handler,
+            error_handler,
+        );
+        assert_eq!(2, status);
+        assert_eq!(vec!["File", "deleted"], output_strings);
+        assert_eq!(vec!["colx: testdata:1: Is a directory"], error_strings);
+    }
+
+    #[test]
+    fn open_fails_with_strict() {
+        let mut output_strings = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let mut error_strings = vec![];
+        let error_handler = |message: String| error_strings.push(message);
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--strict",
+                "1",
+                "testdata/file1",
+                "testdata/does_not_exist",
+                "testdata/file3",
+            ]),
+            output_handler,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert_eq!(Vec::<String>::new(), output_strings);
+        assert_eq!(
+            vec!["colx: testdata/does_not_exist: No such file or directory"],
+            error_strings
+        );
+    }
+
+    #[test]
+    fn check_files_fails_before_output() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert!(message.contains("No such file or directory"));
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--check-files",
+                "1",
+                "testdata/file1",
+                "testdata/does_not_exist",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn multiple_files() {
+        let expected = vec![
+            String::from("This is file 1."),
+            String::from(""),
             String::from("It is not very interesting."),
             String::from("File 2 isn't really any better than file 1."),
             String::from(""),
             String::from(""),
-            String::from("It has more blank lines.  Including a trailing blank line."),
+            String::from("It has more blank lines.  Including a trailing blank line."),
+            String::from(""),
+            String::from(
+                "File 3 is just here to tell you that the next file was Lorem Ipsum but I",
+            ),
+            String::from("deleted it."),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "0",
+                "testdata/file1",
+                "testdata/file2",
+                "testdata/file3",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn header_names() {
+        let expected = vec![
+            String::from("STATUS NAME READY"),
+            String::from("Running web-1 1/1"),
+            String::from("Pending db-1 0/1"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "STATUS",
+                "NAME:READY",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn header_missing_name() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert_eq!("Column name \"UPTIME\" not found in header.", message);
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "NAME",
+                "UPTIME",
+                "testdata/file_with_header",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn csv() {
+        let expected = vec![
+            String::from("name notes"),
+            String::from("Smith, Jane She said \"hi\"\nand left."),
+            String::from("Bloggs, Joe "),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--csv", "1", "--", "-1", "testdata/file.csv"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn csv_with_header() {
+        let expected = vec![
+            String::from("amount"),
+            String::from("1,234.56"),
+            String::from("7"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--csv",
+                "--header",
+                "amount",
+                "testdata/file.csv",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn output_format_csv() {
+        let expected = vec![
+            String::from("\"Smith, Jane\",\"1,234.56\""),
+            String::from("\"Bloggs, Joe\",7"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--csv",
+                "--output-format",
+                "csv",
+                "--header",
+                "name:amount",
+                "testdata/file.csv",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        // The header line is output too.
+        assert_eq!(String::from("name,amount"), output_strings.remove(0));
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn output_format_tsv() {
+        let expected = vec![
+            String::from("She said \"hi\"\\nand left.\tSmith, Jane"),
+            String::from("\tBloggs, Joe"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--csv",
+                "--output-format",
+                "tsv",
+                "--",
+                "-1",
+                "1",
+                "testdata/file.csv",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(String::from("notes\tname"), output_strings.remove(0));
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn output_format_json() {
+        let expected = vec![
+            String::from("[\"This\",\"is\"]"),
+            String::from("[]"),
+            String::from("[\"It\",\"is\"]"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--output-format",
+                "json",
+                "1:2",
+                "testdata/file1",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn output_format_json_with_header() {
+        let expected = vec![
+            String::from("{\"NAME\":\"web-1\",\"AGE\":\"5d\",\"6\":\"extra\"}"),
+            String::from("{\"NAME\":\"db-1\",\"AGE\":\"2h\"}"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "--output-format",
+                "json",
+                "NAME",
+                "AGE",
+                "6",
+                "testdata/file_with_header_and_extra_column",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn invalid_utf8_passed_through() {
+        let expected = vec![b"na\xefve caf\xe9".to_vec(), b"ascii plain".to_vec()];
+        let mut outputs: Vec<Vec<u8>> = vec![];
+        let output_handler = |output: &[u8]| {
+            outputs.push(output.to_vec());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "2:1", "testdata/file_latin1"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, outputs);
+    }
+
+    #[test]
+    fn lossy() {
+        let expected = vec![
+            String::from("caf\u{fffd} na\u{fffd}ve"),
+            String::from("plain ascii"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--lossy", "0", "testdata/file_latin1"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn open_ended_and_stepped_ranges() {
+        let expected = vec![
+            String::from("is file 1. This This file"),
+            String::from(""),
+            String::from("is not very interesting. It It not"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "2:", ":1", "1:4:2", "testdata/file1"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn exclusion() {
+        let expected = vec![
+            String::from("This is 1."),
             String::from(""),
-            String::from(
-                "File 3 is just here to tell you that the next file was Lorem Ipsum but I",
-            ),
-            String::from("deleted it."),
+            String::from("It is very interesting."),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "^3", "testdata/file1"]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn complement_with_header() {
+        let expected = vec![
+            String::from("NAME RESTARTS AGE"),
+            String::from("web-1 0 5d"),
+            String::from("db-1 3 2h"),
         ];
         let mut output_strings: Vec<String> = vec![];
-        let output_handler = |output_string: String| {
-            output_strings.push(output_string);
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
         };
         let status = realmain(
             Flags::parse_from(vec![
                 "argv0",
-                "0",
+                "--complement",
+                "--header",
+                "READY:STATUS",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn keep_empty() {
+        let expected = vec![String::from("root 0 /root"), String::from("nobody 65534 ")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--keep-empty",
+                "--delimiter",
+                ":",
+                "1",
+                "3",
+                "6",
+                "testdata/file_passwd",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn where_predicates() {
+        let expected = vec![String::from("This is"), String::from("It is")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--where",
+                "2==is",
+                "--where",
+                "-1~'\\.$'",
+                "1:2",
                 "testdata/file1",
                 "testdata/file2",
-                "testdata/file3",
             ]),
             output_handler,
             panic_if_called,
         );
-        assert_eq!(0, status);
-        assert_eq!(expected, output_strings);
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn where_with_header() {
+        let expected = vec![String::from("NAME AGE"), String::from("db-1 2h")];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "--where",
+                "STATUS!=Running && RESTARTS>0",
+                "NAME",
+                "AGE",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn where_with_missing_header_name() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert_eq!("Column name \"UPTIME\" not found in header.", message);
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "--where",
+                "UPTIME>1",
+                "NAME",
+                "testdata/file_with_header",
+            ]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn invalid_where() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert_eq!(
+                "Invalid --where predicate \"NAME==a\": column names like NAME require --header",
+                message
+            );
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--where", "NAME==a", "1", "testdata/file1"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn align() {
+        let expected = vec![
+            String::from("NAME  READY RESTARTS"),
+            String::from("web-1 1/1          0"),
+            String::from("db-1  0/1          3"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--align=left,left,numeric",
+                "1:2",
+                "4",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn format() {
+        let expected = vec![
+            String::from("This@file:1.\t{is,file,1.}"),
+            String::from("@:\t{}"),
+            String::from("It@not:interesting.\t{is,not,very,interesting.}"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--separator",
+                ",",
+                "--format",
+                "{1}@{3}:{-1}\\t{{{2:}}}",
+                "testdata/file1",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn format_with_header() {
+        let expected = vec![
+            String::from("NAME=READY STATUS"),
+            String::from("web-1=1/1 Running"),
+            String::from("db-1=0/1 Pending"),
+        ];
+        let mut output_strings: Vec<String> = vec![];
+        let output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let status = realmain(
+            Flags::parse_from(vec![
+                "argv0",
+                "--header",
+                "--format",
+                "{NAME}={2:STATUS}",
+                "testdata/file_with_header",
+            ]),
+            output_handler,
+            panic_if_called,
+        );
+        assert_eq!(0, status);
+        assert_eq!(expected, output_strings);
+    }
+
+    #[test]
+    fn invalid_format() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert_eq!(
+                "Invalid --format template: unterminated placeholder in template",
+                message
+            );
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--format", "{1", "testdata/file1"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn invalid_format_placeholder() {
+        let mut error_handler_called = false;
+        let error_handler = |message: String| {
+            assert_eq!(
+                "Invalid placeholder {x:y:z} in --format: expected a column number at character 1",
+                message
+            );
+            error_handler_called = true;
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--format", "{1}{x:y:z}", "testdata/file1"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+        assert!(error_handler_called);
+    }
+
+    #[test]
+    fn header_no_columns() {
+        let error_handler = |message: String| {
+            assert_eq!(
+                "At least one column or column range must be provided.",
+                message
+            );
+        };
+        let status = realmain(
+            Flags::parse_from(vec!["argv0", "--header", "testdata/file_with_header"]),
+            panic_if_output,
+            error_handler,
+        );
+        assert_eq!(1, status);
+    }
+}
+
+#[cfg(test)]
+mod parse_column_range {
+    use super::*;
+
+    #[test]
+    fn parse_single_column() {
+        assert_eq!(Ok(ColumnRange::new(1, 1)), parse_column_range("1"));
+        assert_eq!(Ok(ColumnRange::new(-2, -2)), parse_column_range("-2"));
+    }
+
+    #[test]
+    fn parse_multiple_columns() {
+        assert_eq!(Ok(ColumnRange::new(1, 7)), parse_column_range("1:7"));
+        assert_eq!(Ok(ColumnRange::new(-6, -2)), parse_column_range("-6:-2"));
+        assert_eq!(Ok(ColumnRange::new(3, -2)), parse_column_range("3:-2"));
+    }
+
+    #[test]
+    fn rejected() {
+        assert!(parse_column_range("a").is_err());
+        assert!(parse_column_range("1.2").is_err());
+        assert!(parse_column_range("1:a").is_err());
+        assert!(parse_column_range("1:2-").is_err());
+        assert!(parse_column_range(":").is_err());
+        assert!(parse_column_range("1::2").is_err());
+        assert!(parse_column_range(":2:1").is_err());
+        assert!(parse_column_range("1:2:0").is_err());
+        assert!(parse_column_range("1:2:-1").is_err());
+        assert!(parse_column_range("1:2:+1").is_err());
+        assert!(parse_column_range("1:2:3:4").is_err());
+        assert!(parse_column_range("F:NR").is_err());
+        assert!(parse_column_range("NF").is_err());
+    }
+
+    #[test]
+    fn error_positions() {
+        let error = |specifier: &str| parse_column_range(specifier).unwrap_err().to_string();
+        assert_eq!("expected a column number at character 1", error("a"));
+        assert_eq!("expected a column number at character 3", error("1::2"));
+        assert_eq!("expected a column number at character 3", error("1:x"));
+        assert_eq!("expected a column number at character 2", error(":"));
+        assert_eq!("expected a column number at character 2", error("^"));
+        assert_eq!("expected a positive step at character 5", error("1:2:0"));
+        assert_eq!("expected a positive step at character 5", error("1:2:"));
+        assert_eq!(
+            "expected the end of the column specifier at character 6",
+            error("1:2:3:4")
+        );
+        assert_eq!(
+            "expected a column number with fewer digits at character 3",
+            error("1:99999999999999999999")
+        );
+        // Positions count characters rather than bytes.
+        assert_eq!(
+            "expected a column number or name at character 3",
+            parse_named_column_range("é::1").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn virtual_columns() {
+        assert_eq!(
+            Ok(ColumnRange::virtual_column(VirtualColumn::Filename, false)),
+            parse_column_range("F")
+        );
+        assert_eq!(
+            Ok(ColumnRange::virtual_column(VirtualColumn::LineNumber, true)),
+            parse_column_range("^N")
+        );
+        assert_eq!(
+            Ok(ColumnRange::virtual_column(
+                VirtualColumn::RecordNumber,
+                false
+            )),
+            parse_column_range("NR")
+        );
+    }
+
+    #[test]
+    fn parse_open_ended() {
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(3),
+                end: None,
+                step: 1,
+                exclude: false,
+                virtual_column: None,
+            }),
+            parse_column_range("3:")
+        );
+        assert_eq!(
+            Ok(ColumnRange {
+                start: None,
+                end: Some(5),
+                step: 1,
+                exclude: false,
+                virtual_column: None,
+            }),
+            parse_column_range(":5")
+        );
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(-3),
+                end: None,
+                step: 1,
+                exclude: false,
+                virtual_column: None,
+            }),
+            parse_column_range("-3:")
+        );
+    }
+
+    #[test]
+    fn parse_excluded() {
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(-1),
+                end: Some(-1),
+                step: 1,
+                exclude: true,
+                virtual_column: None,
+            }),
+            parse_column_range("^-1")
+        );
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(2),
+                end: None,
+                step: 1,
+                exclude: true,
+                virtual_column: None,
+            }),
+            parse_column_range("^2:")
+        );
+        assert!(parse_column_range("^").is_err());
+        assert!(parse_column_range("^^1").is_err());
+        assert!(parse_column_range("1^").is_err());
+    }
+
+    #[test]
+    fn parse_stepped() {
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(1),
+                end: Some(20),
+                step: 2,
+                exclude: false,
+                virtual_column: None,
+            }),
+            parse_column_range("1:20:2")
+        );
+        assert_eq!(
+            Ok(ColumnRange {
+                start: Some(-1),
+                end: Some(1),
+                step: 3,
+                exclude: false,
+                virtual_column: None,
+            }),
+            parse_column_range("-1:1:3")
+        );
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert!(
+            parse_column_range("9999999999999999999999999999999999999999999999999999999999:1")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn integer_overflow_multiple() {
+        assert!(
+            parse_column_range("1:9999999999999999999999999999999999999999999999999999999999")
+                .is_err()
+        );
     }
 }
 
 #[cfg(test)]
-mod parse_column_range {
+mod parse_named_column_range {
     use super::*;
 
     #[test]
-    fn parse_single_column() {
+    fn numbers() {
         assert_eq!(
-            Some(ColumnRange { start: 1, end: 1 }),
-            parse_column_range("1")
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Number(-3)),
+                end: Some(ColumnBound::Number(2)),
+                step: 1,
+                exclude: false,
+            }),
+            parse_named_column_range("-3:2")
         );
+    }
+
+    #[test]
+    fn single_name() {
         assert_eq!(
-            Some(ColumnRange { start: -2, end: -2 }),
-            parse_column_range("-2")
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: Some(ColumnBound::Name(String::from("NAME"))),
+                step: 1,
+                exclude: false,
+            }),
+            parse_named_column_range("NAME")
         );
     }
 
     #[test]
-    fn parse_multiple_columns() {
+    fn mixed_range() {
         assert_eq!(
-            Some(ColumnRange { start: 1, end: 7 }),
-            parse_column_range("1:7")
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: Some(ColumnBound::Number(-1)),
+                step: 1,
+                exclude: false,
+            }),
+            parse_named_column_range("NAME:-1")
         );
+    }
+
+    #[test]
+    fn open_ended() {
+        assert_eq!(
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: None,
+                step: 1,
+                exclude: false,
+            }),
+            parse_named_column_range("NAME:")
+        );
+        assert_eq!(
+            Ok(NamedColumnRange {
+                start: None,
+                end: Some(ColumnBound::Name(String::from("AGE"))),
+                step: 1,
+                exclude: false,
+            }),
+            parse_named_column_range(":AGE")
+        );
+    }
+
+    #[test]
+    fn stepped() {
         assert_eq!(
-            Some(ColumnRange { start: -6, end: -2 }),
-            parse_column_range("-6:-2")
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: Some(ColumnBound::Number(-1)),
+                step: 2,
+                exclude: false,
+            }),
+            parse_named_column_range("NAME:-1:2")
         );
+    }
+
+    #[test]
+    fn excluded() {
         assert_eq!(
-            Some(ColumnRange { start: 3, end: -2 }),
-            parse_column_range("3:-2")
+            Ok(NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: Some(ColumnBound::Name(String::from("AGE"))),
+                step: 1,
+                exclude: true,
+            }),
+            parse_named_column_range("^NAME:AGE")
         );
     }
 
     #[test]
     fn rejected() {
-        assert_eq!(None, parse_column_range("a"));
-        assert_eq!(None, parse_column_range("1.2"));
-        assert_eq!(None, parse_column_range("1:a"));
-        assert_eq!(None, parse_column_range("1:2-"));
-        assert_eq!(None, parse_column_range(":2"));
-        assert_eq!(None, parse_column_range("1:"));
+        assert!(parse_named_column_range("").is_err());
+        assert!(parse_named_column_range(":").is_err());
+        assert!(parse_named_column_range("A:B:C").is_err());
+        assert!(parse_named_column_range("A:B:1:2").is_err());
+        assert!(parse_named_column_range("A::2").is_err());
+        assert!(parse_named_column_range("9999999999999999999999999999999999999999:NAME").is_err());
     }
+}
+
+#[cfg(test)]
+mod separate_named_args {
+    use super::*;
 
     #[test]
-    fn integer_overflow() {
+    fn names_then_files() {
+        let (actual_columns, actual_filenames) = separate_named_args(vec![
+            String::from("NAME"),
+            String::from("2"),
+            String::from("testdata/file1"),
+            String::from("STATUS"),
+        ])
+        .unwrap();
+        let expected_columns = vec![
+            NamedColumnRange {
+                start: Some(ColumnBound::Name(String::from("NAME"))),
+                end: Some(ColumnBound::Name(String::from("NAME"))),
+                step: 1,
+                exclude: false,
+            },
+            NamedColumnRange {
+                start: Some(ColumnBound::Number(2)),
+                end: Some(ColumnBound::Number(2)),
+                step: 1,
+                exclude: false,
+            },
+        ];
+        assert_eq!(expected_columns, actual_columns);
+        let expected_filenames = vec![String::from("testdata/file1"), String::from("STATUS")];
+        assert_eq!(expected_filenames, actual_filenames);
+    }
+
+    #[test]
+    fn stdin_ends_columns() {
+        let (actual_columns, actual_filenames) =
+            separate_named_args(vec![String::from("NAME"), String::from("-")]).unwrap();
+        assert_eq!(1, actual_columns.len());
+        assert_eq!(vec![String::from("-")], actual_filenames);
+    }
+
+    #[test]
+    fn no_files() {
+        let (actual_columns, actual_filenames) =
+            separate_named_args(vec![String::from("NAME"), String::from("AGE")]).unwrap();
+        assert_eq!(2, actual_columns.len());
+        assert_eq!(Vec::<String>::new(), actual_filenames);
+    }
+
+    #[test]
+    fn malformed_column_is_an_error() {
         assert_eq!(
-            None,
-            parse_column_range("9999999999999999999999999999999999999999999999999999999999:1")
+            Err(String::from(
+                "Invalid column specifier \"NAME::AGE\": expected a column number or name at \
+                 character 6"
+            )),
+            separate_named_args(vec![
+                String::from("NAME::AGE"),
+                String::from("testdata/file1")
+            ])
+        );
+    }
+}
+
+#[cfg(test)]
+mod resolve_column_names {
+    use super::*;
+
+    fn named(start: &str, end: &str) -> NamedColumnRange {
+        parse_named_column_range(&format!("{start}:{end}")).unwrap()
+    }
+
+    #[test]
+    fn names_and_numbers() {
+        let header = ["NAME READY STATUS", "NAME", "READY", "STATUS"];
+        let expected = vec![
+            ColumnRange::new(3, 3),
+            ColumnRange::new(1, -1),
+            ColumnRange::new(2, 1),
+        ];
+        let actual = resolve_column_names(
+            &[
+                named("STATUS", "STATUS"),
+                named("NAME", "-1"),
+                named("READY", "NAME"),
+            ],
+            &header,
         );
+        assert_eq!(Ok(expected), actual);
     }
 
     #[test]
-    fn integer_overflow_multiple() {
+    fn virtual_columns() {
+        let header = ["NAME N", "NAME", "N"];
+        let expected = vec![
+            ColumnRange::new(1, 1),
+            ColumnRange::virtual_column(VirtualColumn::RecordNumber, false),
+        ];
+        let actual = resolve_column_names(&[named("NAME", "NAME"), named("NR", "NR")], &header);
+        assert_eq!(Ok(expected), actual);
+    }
+
+    #[test]
+    fn header_names_take_precedence_over_virtual_columns() {
+        let header = ["F S PID", "F", "S", "PID"];
+        let actual = resolve_column_names(&[named("F", "F"), named("PID", "PID")], &header);
         assert_eq!(
-            None,
-            parse_column_range("1:9999999999999999999999999999999999999999999999999999999999")
+            Ok(vec![ColumnRange::new(1, 1), ColumnRange::new(3, 3)]),
+            actual
+        );
+        let header = ["F F", "F", "F"];
+        assert!(resolve_column_names(&[named("F", "F")], &header).is_err());
+    }
+
+    #[test]
+    fn whole_line_is_not_a_name() {
+        let header = ["NAME", "NAME"];
+        let actual = resolve_column_names(&[named("NAME", "NAME")], &header);
+        assert_eq!(Ok(vec![ColumnRange::new(1, 1)]), actual);
+    }
+
+    #[test]
+    fn missing_name() {
+        let header = ["NAME READY", "NAME", "READY"];
+        let actual = resolve_column_names(&[named("NAME", "AGE")], &header);
+        assert_eq!(
+            Err(String::from("Column name \"AGE\" not found in header.")),
+            actual
+        );
+    }
+
+    #[test]
+    fn ambiguous_name() {
+        let header = ["NAME STATUS NAME", "NAME", "STATUS", "NAME"];
+        let actual = resolve_column_names(&[named("NAME", "NAME")], &header);
+        assert_eq!(
+            Err(String::from(
+                "Column name \"NAME\" is ambiguous: it matches header columns 1, 3."
+            )),
+            actual
         );
     }
 }
@@ -709,7 +3966,7 @@ mod separate_args {
 
     #[test]
     fn no_args() {
-        let (columns, filenames) = separate_args(vec![]);
+        let (columns, filenames) = separate_args(vec![]).unwrap();
         assert_eq!(Vec::<ColumnRange>::new(), columns);
         assert_eq!(Vec::<String>::new(), filenames);
     }
@@ -722,11 +3979,9 @@ mod separate_args {
             String::from("foo"),
             String::from("bar"),
             String::from("baz"),
-        ]);
-        let expected_columns = vec![
-            ColumnRange { start: 1, end: 1 },
-            ColumnRange { start: 4, end: -2 },
-        ];
+        ])
+        .unwrap();
+        let expected_columns = vec![ColumnRange::new(1, 1), ColumnRange::new(4, -2)];
         assert_eq!(expected_columns, actual_columns);
         let expected_filenames = vec![
             String::from("foo"),
@@ -744,8 +3999,9 @@ mod separate_args {
             String::from("bar"),
             String::from("1"),
             String::from("baz"),
-        ]);
-        let expected_columns = vec![ColumnRange { start: 4, end: -2 }];
+        ])
+        .unwrap();
+        let expected_columns = vec![ColumnRange::new(4, -2)];
         assert_eq!(expected_columns, actual_columns);
         let expected_filenames = vec![
             String::from("foo"),
@@ -762,78 +4018,443 @@ mod separate_args {
             String::from("1"),
             String::from("invalid"),
             String::from("2"),
-        ]);
-        let expected_columns = vec![ColumnRange { start: 1, end: 1 }];
+        ])
+        .unwrap();
+        let expected_columns = vec![ColumnRange::new(1, 1)];
         assert_eq!(expected_columns, actual_columns);
         let expected_filenames = vec![String::from("invalid"), String::from("2")];
         assert_eq!(expected_filenames, actual_filenames);
     }
+
+    #[test]
+    fn malformed_column_is_an_error() {
+        assert_eq!(
+            Err(String::from(
+                "Invalid column specifier \"1::2\": expected a column number at character 3"
+            )),
+            separate_args(vec![String::from("1"), String::from("1::2")])
+        );
+        assert!(separate_args(vec![String::from("^x")]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod column_numbers {
+    use super::*;
+
+    fn range(start: Option<isize>, end: Option<isize>, step: usize) -> ColumnRange {
+        ColumnRange {
+            start,
+            end,
+            step,
+            exclude: false,
+            virtual_column: None,
+        }
+    }
+
+    fn numbers(range: ColumnRange, num_columns: usize) -> Vec<isize> {
+        range.column_numbers(num_columns).collect()
+    }
+
+    #[test]
+    fn closed_ranges() {
+        assert_eq!(vec![-2, -1, 0, 1], numbers(ColumnRange::new(-2, 1), 3));
+        assert_eq!(vec![3, 2, 1], numbers(ColumnRange::new(5, 1), 3));
+        assert_eq!(vec![-3, -2], numbers(ColumnRange::new(-5, -2), 3));
+        assert_eq!(Vec::<isize>::new(), numbers(ColumnRange::new(9, 8), 3));
+    }
+
+    #[test]
+    fn huge_closed_ranges() {
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            numbers(ColumnRange::new(0, isize::MAX), 3)
+        );
+        assert_eq!(vec![1, 2, 3], numbers(ColumnRange::new(1, isize::MAX), 3));
+        assert_eq!(
+            vec![-3, -2, -1, 0, 1, 2, 3],
+            numbers(ColumnRange::new(isize::MIN, isize::MAX), 3)
+        );
+        assert_eq!(
+            vec![3, 2, 1, 0, -1, -2, -3],
+            numbers(ColumnRange::new(isize::MAX, isize::MIN), 3)
+        );
+        assert_eq!(
+            Vec::<isize>::new(),
+            numbers(range(Some(isize::MAX), Some(isize::MAX), usize::MAX), 3)
+        );
+    }
+
+    #[test]
+    fn stepped() {
+        assert_eq!(vec![1, 3, 5], numbers(range(Some(1), Some(6), 2), 10));
+        assert_eq!(vec![6, 3], numbers(range(Some(6), Some(1), 3), 10));
+        assert_eq!(vec![-1], numbers(range(Some(-1), Some(1), 3), 10));
+        assert_eq!(vec![-2, 2], numbers(range(Some(-10), Some(2), 4), 3));
+        assert_eq!(vec![2, -2], numbers(range(Some(10), Some(-9), 4), 3));
+        assert_eq!(
+            vec![1],
+            numbers(range(Some(1), Some(isize::MAX), usize::MAX), 3)
+        );
+    }
+
+    #[test]
+    fn open_end() {
+        assert_eq!(vec![3, 4, 5], numbers(range(Some(3), None, 1), 6));
+        assert_eq!(vec![3, 4, 5], numbers(range(Some(-3), None, 1), 6));
+        assert_eq!(vec![1, 2, 3, 4, 5], numbers(range(Some(-9), None, 1), 6));
+        assert_eq!(vec![0, 1, 2], numbers(range(Some(0), None, 1), 3));
+        assert_eq!(Vec::<isize>::new(), numbers(range(Some(7), None, 1), 6));
+    }
+
+    #[test]
+    fn open_start() {
+        assert_eq!(vec![1, 2], numbers(range(None, Some(2), 1), 6));
+        assert_eq!(vec![1, 2, 3, 4], numbers(range(None, Some(-2), 1), 6));
+        assert_eq!(vec![1, 2, 3, 4, 5], numbers(range(None, Some(9), 1), 6));
+        assert_eq!(Vec::<isize>::new(), numbers(range(None, Some(-9), 1), 6));
+    }
+
+    #[test]
+    fn open_both() {
+        assert_eq!(vec![1, 3, 5], numbers(range(None, None, 2), 6));
+        assert_eq!(Vec::<isize>::new(), numbers(range(None, None, 1), 1));
+        assert_eq!(Vec::<isize>::new(), numbers(range(None, None, 1), 0));
+    }
+}
+
+#[cfg(test)]
+mod parse_column_list {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        let (column_ranges, named_ranges) = parse_column_list("1,3:5,-1", false).unwrap();
+        assert_eq!(
+            vec![
+                ColumnRange::new(1, 1),
+                ColumnRange::new(3, 5),
+                ColumnRange::new(-1, -1)
+            ],
+            column_ranges
+        );
+        assert_eq!(Vec::<NamedColumnRange>::new(), named_ranges);
+    }
+
+    #[test]
+    fn names() {
+        let (column_ranges, named_ranges) = parse_column_list("NAME,2", true).unwrap();
+        assert_eq!(Vec::<ColumnRange>::new(), column_ranges);
+        assert_eq!(2, named_ranges.len());
+    }
+
+    #[test]
+    fn error_positions_count_from_the_start_of_the_list() {
+        assert_eq!(
+            Err(String::from(
+                "Invalid --columns list \"1,,3\": expected a column number at character 3"
+            )),
+            parse_column_list("1,,3", false)
+        );
+        assert_eq!(
+            Err(String::from(
+                "Invalid --columns list \"1,2:x\": expected a column number at character 5"
+            )),
+            parse_column_list("1,2:x", false)
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse_placeholders {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        let placeholders = vec![String::from("1"), String::from("-2:")];
+        let expected = vec![
+            ColumnRange::new(1, 1),
+            ColumnRange {
+                start: Some(-2),
+                end: None,
+                step: 1,
+                exclude: false,
+                virtual_column: None,
+            },
+        ];
+        assert_eq!(
+            Ok((expected, vec![])),
+            parse_placeholders(&placeholders, false)
+        );
+    }
+
+    #[test]
+    fn names() {
+        let placeholders = vec![String::from("NAME"), String::from("2")];
+        let (column_ranges, named_ranges) = parse_placeholders(&placeholders, true).unwrap();
+        assert_eq!(Vec::<ColumnRange>::new(), column_ranges);
+        assert_eq!(2, named_ranges.len());
+    }
+
+    #[test]
+    fn invalid() {
+        let placeholders = vec![String::from("1"), String::from("NAME")];
+        assert_eq!(
+            Err(String::from(
+                "Invalid placeholder {NAME} in --format: expected a column number at character 1"
+            )),
+            parse_placeholders(&placeholders, false)
+        );
+        let placeholders = vec![String::from("A:B:C")];
+        assert_eq!(
+            Err(String::from(
+                "Invalid placeholder {A:B:C} in --format: expected a positive step at character 5"
+            )),
+            parse_placeholders(&placeholders, true)
+        );
+    }
+}
+
+#[cfg(test)]
+mod extract_indices {
+    use super::*;
+
+    #[test]
+    fn indices() {
+        let column_ranges = [
+            ColumnRange::new(2, 1),
+            ColumnRange::new(-1, -1),
+            ColumnRange::new(9, 9),
+        ];
+        assert_eq!(vec![2, 1, 3], extract_indices(&column_ranges, 4));
+    }
+
+    #[test]
+    fn virtual_columns_follow_real_columns() {
+        let column_ranges = [
+            ColumnRange::virtual_column(VirtualColumn::RecordNumber, false),
+            ColumnRange::new(1, 3),
+            ColumnRange::virtual_column(VirtualColumn::Filename, false),
+        ];
+        assert_eq!(vec![6, 1, 2, 3, 4], extract_indices(&column_ranges, 4));
+    }
+
+    fn excluded(start: isize, end: isize) -> ColumnRange {
+        ColumnRange {
+            exclude: true,
+            virtual_column: None,
+            ..ColumnRange::new(start, end)
+        }
+    }
+
+    #[test]
+    fn only_exclusions() {
+        assert_eq!(vec![1, 2, 3], extract_indices(&[excluded(-1, -1)], 5));
+        assert_eq!(vec![1, 4], extract_indices(&[excluded(2, 3)], 5));
+        assert_eq!(
+            vec![2, 4],
+            extract_indices(&[excluded(1, 1), excluded(3, 3)], 5)
+        );
+        assert_eq!(Vec::<usize>::new(), extract_indices(&[excluded(9, 9)], 1));
+    }
+
+    #[test]
+    fn inclusions_and_exclusions() {
+        let column_ranges = [
+            ColumnRange::new(4, 1),
+            excluded(-2, -2),
+            ColumnRange::new(1, 1),
+        ];
+        assert_eq!(vec![4, 2, 1, 1], extract_indices(&column_ranges, 5));
+    }
+
+    #[test]
+    fn no_ranges() {
+        assert_eq!(Vec::<usize>::new(), extract_indices(&[], 5));
+    }
+}
+
+#[cfg(test)]
+mod join_preserving_delimiters_into {
+    use super::*;
+
+    fn join_spans(column_ranges: &[ColumnRange], line: &[u8], spans: &[Range<usize>]) -> Vec<u8> {
+        let virtual_columns: Vec<&[u8]> = vec![b"file", b"1", b"1"];
+        let mut output = b"replaced".to_vec();
+        join_preserving_delimiters_into(
+            column_ranges,
+            Columns::new(line, spans),
+            &virtual_columns,
+            b",",
+            &mut IndexBuffers::default(),
+            &mut output,
+        );
+        output
+    }
+
+    fn join(column_ranges: &[ColumnRange], line: &str, keep_empty: bool) -> String {
+        let delimiter = Delimiter::regex(r"\s+").unwrap();
+        let mut spans = vec![];
+        split_line(&delimiter, line.as_bytes(), keep_empty, &mut spans);
+        String::from_utf8(join_spans(column_ranges, line.as_bytes(), &spans)).unwrap()
+    }
+
+    #[test]
+    fn adjacent_columns_keep_delimiters() {
+        assert_eq!(
+            "b  c\td",
+            join(&[ColumnRange::new(2, 4)], "a b  c\td e", false)
+        );
+        let exclude_3 = ColumnRange {
+            exclude: true,
+            ..ColumnRange::new(3, 3)
+        };
+        assert_eq!("a b", join(&[exclude_3], "a b c", false));
+    }
+
+    #[test]
+    fn other_columns_use_separator() {
+        assert_eq!(
+            "a,c",
+            join(
+                &[ColumnRange::new(1, 1), ColumnRange::new(3, 3)],
+                "a b c",
+                false
+            )
+        );
+        assert_eq!("c,b", join(&[ColumnRange::new(3, 2)], "a b c", false));
+        assert_eq!(
+            "b  c,d",
+            join(
+                &[ColumnRange::new(2, 3), ColumnRange::new(4, 4)],
+                "a b  c\td e",
+                false
+            )
+        );
+        assert_eq!("a b,a", join(&[ColumnRange::new(0, 1)], "a b", false));
+        assert_eq!(
+            "a,file",
+            join(
+                &[
+                    ColumnRange::new(1, 1),
+                    ColumnRange::virtual_column(VirtualColumn::Filename, false)
+                ],
+                "a",
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn discarded_empty_columns() {
+        assert_eq!(" a ", join(&[ColumnRange::new(1, 3)], " a ", true));
+        assert_eq!("a", join(&[ColumnRange::new(1, 3)], " a ", false));
+    }
+
+    #[test]
+    fn trimmed_fixed_width_columns() {
+        let fixed_width = FixedWidth::from_widths(&[4, 4], false, true);
+        let line = b"ab  cd  ef";
+        let mut spans = vec![];
+        fixed_width.split_ranges(line, &mut spans);
+        assert_eq!(
+            b"cd  ef".to_vec(),
+            join_spans(&[ColumnRange::new(2, 3)], line, &spans)
+        );
+    }
 }
 
 #[cfg(test)]
 mod extract_columns {
     use super::*;
 
+    fn extract_columns(
+        column_ranges: &[ColumnRange],
+        columns: &[&str],
+        virtual_columns: &[&str],
+    ) -> Vec<String> {
+        let (text, spans) = text_and_spans(columns);
+        let virtual_columns: Vec<&[u8]> = virtual_columns.iter().map(|v| v.as_bytes()).collect();
+        super::extract_columns(column_ranges, Columns::new(&text, &spans), &virtual_columns)
+            .into_iter()
+            .map(|column| String::from_utf8(column.to_vec()).unwrap())
+            .collect()
+    }
+
     #[test]
     fn single_column() {
         let expected = vec!["asdf"];
-        let column_ranges = [ColumnRange { start: 1, end: 1 }];
+        let column_ranges = [ColumnRange::new(1, 1)];
         let columns = ["ignored", "asdf", "ignored"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn virtual_columns() {
+        let expected = vec!["file", "asdf", "42"];
+        let column_ranges = [
+            ColumnRange::virtual_column(VirtualColumn::Filename, false),
+            ColumnRange::new(1, 1),
+            ColumnRange::virtual_column(VirtualColumn::RecordNumber, false),
+        ];
+        let columns = ["asdf", "asdf"];
+        let virtual_columns = ["file", "7", "42"];
+        let actual = extract_columns(&column_ranges, &columns, &virtual_columns);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn negative_index_empty_columns() {
         let expected: Vec<&str> = vec![];
-        let column_ranges = [ColumnRange { start: -1, end: -1 }];
+        let column_ranges = [ColumnRange::new(-1, -1)];
         let columns: [&str; 0] = [];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn empty_columns_slice() {
         let expected: Vec<&str> = vec![];
-        let column_ranges = [ColumnRange { start: 1, end: 1 }];
+        let column_ranges = [ColumnRange::new(1, 1)];
         let columns: [&str; 0] = [];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn out_of_bounds_column() {
         let expected: Vec<&str> = vec![];
-        let column_ranges = [ColumnRange { start: 7, end: 7 }];
+        let column_ranges = [ColumnRange::new(7, 7)];
         let columns = ["ignored", "ignored", "ignored"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn single_range() {
         let expected = vec!["two", "three", "four"];
-        let column_ranges = [ColumnRange { start: 2, end: 4 }];
+        let column_ranges = [ColumnRange::new(2, 4)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn partially_out_of_bounds_range() {
         let expected = vec!["two", "three", "four", "five"];
-        let column_ranges = [ColumnRange { start: 2, end: 6 }];
+        let column_ranges = [ColumnRange::new(2, 6)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn negative_index() {
         let expected = vec!["four", "five", "zero", "one", "two", "three"];
-        let column_ranges = [ColumnRange { start: -2, end: 3 }];
+        let column_ranges = [ColumnRange::new(-2, 3)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
@@ -842,37 +4463,31 @@ mod extract_columns {
         let expected = vec![
             "two", "three", "four", "one", "two", "three", "four", "five",
         ];
-        let column_ranges = [
-            ColumnRange { start: 2, end: 4 },
-            ColumnRange { start: 1, end: 5 },
-        ];
+        let column_ranges = [ColumnRange::new(2, 4), ColumnRange::new(1, 5)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn really_out_of_bounds_indices() {
-        let column_ranges = [ColumnRange {
-            start: -20,
-            end: 20,
-        }];
+        let column_ranges = [ColumnRange::new(-20, 20)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
         // We'll get all the columns once using -6:-1 and a second time using 0:5.
         let expected = vec![
             "zero", "one", "two", "three", "four", "five", "zero", "one", "two", "three", "four",
             "five",
         ];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn reversed_indices() {
         let expected = vec!["four", "three", "two"];
-        let column_ranges = [ColumnRange { start: 4, end: 2 }];
+        let column_ranges = [ColumnRange::new(4, 2)];
         let columns = ["zero", "one", "two", "three", "four", "five"];
-        let actual = extract_columns(&column_ranges, &columns);
+        let actual = extract_columns(&column_ranges, &columns, &[]);
         assert_eq!(expected, actual);
     }
 }
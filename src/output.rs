@@ -0,0 +1,531 @@
+//! Formatting of extracted columns for output.
+
+use std::io::BufWriter;
+use std::io::Write;
+
+use clap::ValueEnum;
+use unicode_width::UnicodeWidthStr;
+
+/// The supported output formats.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Columns joined with --separator.
+    Plain,
+    /// RFC 4180 CSV, quoting columns when necessary.
+    Csv,
+    /// Tab separated, escaping backslash, tab, newline, and carriage return.
+    Tsv,
+    /// JSON Lines: an array per line, or an object keyed by header names with --header.
+    Json,
+}
+
+/// Formats columns as a CSV record into record, replacing its contents.  Columns containing commas,
+/// double quotes, or line breaks are quoted, and double quotes inside them are doubled.
+pub fn format_csv_into<'a>(columns: impl IntoIterator<Item = &'a [u8]>, record: &mut Vec<u8>) {
+    record.clear();
+    for (i, column) in columns.into_iter().enumerate() {
+        if i > 0 {
+            record.push(b',');
+        }
+        if column
+            .iter()
+            .any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r'))
+        {
+            record.push(b'"');
+            for &b in column {
+                if b == b'"' {
+                    record.push(b'"');
+                }
+                record.push(b);
+            }
+            record.push(b'"');
+        } else {
+            record.extend_from_slice(column);
+        }
+    }
+}
+
+/// Formats columns as a TSV record into record, replacing its contents, and escaping characters
+/// that would otherwise be ambiguous.
+pub fn format_tsv_into<'a>(columns: impl IntoIterator<Item = &'a [u8]>, record: &mut Vec<u8>) {
+    record.clear();
+    for (i, column) in columns.into_iter().enumerate() {
+        if i > 0 {
+            record.push(b'\t');
+        }
+        for &b in column {
+            match b {
+                b'\\' => record.extend_from_slice(b"\\\\"),
+                b'\t' => record.extend_from_slice(b"\\t"),
+                b'\n' => record.extend_from_slice(b"\\n"),
+                b'\r' => record.extend_from_slice(b"\\r"),
+                _ => record.push(b),
+            }
+        }
+    }
+}
+
+/// Appends a quoted and escaped JSON string to output.  JSON must be valid UTF-8, so invalid UTF-8
+/// sequences are replaced with U+FFFD REPLACEMENT CHARACTER.
+fn push_json_string(output: &mut Vec<u8>, unescaped: &[u8]) {
+    output.push(b'"');
+    for c in String::from_utf8_lossy(unescaped).chars() {
+        match c {
+            '"' => output.extend_from_slice(b"\\\""),
+            '\\' => output.extend_from_slice(b"\\\\"),
+            '\n' => output.extend_from_slice(b"\\n"),
+            '\r' => output.extend_from_slice(b"\\r"),
+            '\t' => output.extend_from_slice(b"\\t"),
+            c if c < ' ' => write!(output, "\\u{:04x}", u32::from(c)).unwrap(),
+            _ => output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    output.push(b'"');
+}
+
+/// Formats columns as a JSON array of strings into array, replacing its contents.
+pub fn format_json_array_into<'a>(
+    columns: impl IntoIterator<Item = &'a [u8]>,
+    array: &mut Vec<u8>,
+) {
+    array.clear();
+    array.push(b'[');
+    for (i, column) in columns.into_iter().enumerate() {
+        if i > 0 {
+            array.push(b',');
+        }
+        push_json_string(array, column);
+    }
+    array.push(b']');
+}
+
+/// The key of a column in a JSON object: its name, or its column number if it has no name.
+pub enum JsonKey<'a> {
+    Name(&'a [u8]),
+    Number(usize),
+}
+
+/// Formats pairs of keys and columns as a JSON object into object, replacing its contents.
+pub fn format_json_object_into<'a>(
+    keys_and_columns: impl IntoIterator<Item = (JsonKey<'a>, &'a [u8])>,
+    object: &mut Vec<u8>,
+) {
+    object.clear();
+    object.push(b'{');
+    for (i, (key, column)) in keys_and_columns.into_iter().enumerate() {
+        if i > 0 {
+            object.push(b',');
+        }
+        match key {
+            JsonKey::Name(name) => push_json_string(object, name),
+            JsonKey::Number(number) => write!(object, "\"{number}\"").unwrap(),
+        }
+        object.push(b':');
+        push_json_string(object, column);
+    }
+    object.push(b'}');
+}
+
+/// How --align aligns a column.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Alignment {
+    /// Pad on the right.
+    #[value(alias = "l")]
+    Left,
+    /// Pad on the left.
+    #[value(alias = "r")]
+    Right,
+    /// Right align numbers and left align everything else.
+    #[value(alias = "n")]
+    Numeric,
+}
+
+/// Returns the display width of a column, e.g. 2 for most CJK characters.  Invalid UTF-8 sequences
+/// are counted as U+FFFD REPLACEMENT CHARACTER.
+fn display_width(column: &[u8]) -> usize {
+    String::from_utf8_lossy(column).width()
+}
+
+/// Returns whether a column is a number for [Alignment::Numeric].
+fn is_number(column: &[u8]) -> bool {
+    std::str::from_utf8(column)
+        .ok()
+        .and_then(|column| column.trim().parse::<f64>().ok())
+        .is_some()
+}
+
+/// Pads columns so that they line up in a table.  Lines are buffered until buffer_lines lines have
+/// been pushed (or forever if buffer_lines is None), then the width of each column is calculated and
+/// the buffered lines are output.  Widths never shrink, so later lines will still line up if their
+/// columns are narrower than earlier lines, but not if they are wider.
+pub struct Aligner {
+    alignments: Vec<Alignment>,
+    separator: Vec<u8>,
+    buffer_lines: Option<usize>,
+    lines: Vec<Vec<Vec<u8>>>,
+    widths: Vec<usize>,
+}
+
+impl Aligner {
+    /// alignments[i] is used for column i, and the last alignment is used for any remaining
+    /// columns.  Columns are joined with separator.
+    pub fn new(alignments: Vec<Alignment>, separator: &[u8], buffer_lines: Option<usize>) -> Self {
+        Self {
+            alignments,
+            separator: separator.to_vec(),
+            buffer_lines,
+            lines: vec![],
+            widths: vec![],
+        }
+    }
+
+    /// Buffers a line of columns, outputting all buffered lines if the buffer is full.
+    pub fn push<OH: FnMut(&[u8])>(&mut self, columns: Vec<Vec<u8>>, output_handler: &mut OH) {
+        self.lines.push(columns);
+        if Some(self.lines.len()) == self.buffer_lines {
+            self.flush(output_handler);
+        }
+    }
+
+    /// Outputs all buffered lines.  This must be called after the last line has been pushed.
+    pub fn flush<OH: FnMut(&[u8])>(&mut self, output_handler: &mut OH) {
+        for columns in self.lines.iter() {
+            for (i, column) in columns.iter().enumerate() {
+                let width = display_width(column);
+                match self.widths.get_mut(i) {
+                    Some(max_width) => *max_width = width.max(*max_width),
+                    None => self.widths.push(width),
+                }
+            }
+        }
+        for columns in std::mem::take(&mut self.lines) {
+            output_handler(&self.format_line(&columns));
+        }
+    }
+
+    /// Pads and joins a line of columns.  The last column isn't padded on the right so that lines
+    /// don't have trailing whitespace.
+    fn format_line(&self, columns: &[Vec<u8>]) -> Vec<u8> {
+        let mut line = vec![];
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                line.extend_from_slice(&self.separator);
+            }
+            let padding = self.widths[i] - display_width(column);
+            let alignment = match self.alignments.get(i).or(self.alignments.last()) {
+                Some(Alignment::Numeric) if is_number(column) => Alignment::Right,
+                Some(Alignment::Right) => Alignment::Right,
+                _ => Alignment::Left,
+            };
+            if alignment == Alignment::Right {
+                line.resize(line.len() + padding, b' ');
+            }
+            line.extend_from_slice(column);
+            if alignment == Alignment::Left && i + 1 < columns.len() {
+                line.resize(line.len() + padding, b' ');
+            }
+        }
+        line
+    }
+}
+
+/// Writes output records, each followed by a terminator, through a buffer so that the underlying
+/// writer (usually locked stdout) isn't written to for every record.  With line_buffered, e.g. when
+/// stdout is a terminal, the buffer is flushed after every record so that output appears
+/// immediately; otherwise it's flushed when it's full, and flush() must be called at the end.
+pub struct RecordWriter<W: Write> {
+    writer: BufWriter<W>,
+    terminator: Vec<u8>,
+    line_buffered: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, terminator: Vec<u8>, line_buffered: bool) -> Self {
+        Self {
+            writer: BufWriter::with_capacity(64 * 1024, writer),
+            terminator,
+            line_buffered,
+        }
+    }
+
+    /// Writes a record followed by the terminator.
+    pub fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(record)?;
+        self.writer.write_all(&self.terminator)?;
+        if self.line_buffered {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes any buffered records.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod format_csv {
+    use super::*;
+
+    fn format_csv<T: AsRef<[u8]>>(columns: &[T]) -> Vec<u8> {
+        let mut record = b"replaced".to_vec();
+        format_csv_into(columns.iter().map(AsRef::as_ref), &mut record);
+        record
+    }
+
+    #[test]
+    fn unquoted() {
+        assert_eq!("a,b c,".as_bytes(), format_csv(&["a", "b c", ""]));
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(
+            "\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\"cr\r\"".as_bytes(),
+            format_csv(&["a,b", "say \"hi\"", "two\nlines", "cr\r"])
+        );
+    }
+
+    #[test]
+    fn no_columns() {
+        assert_eq!("".as_bytes(), format_csv::<&str>(&[]));
+    }
+}
+
+#[cfg(test)]
+mod format_tsv {
+    use super::*;
+
+    fn format_tsv<T: AsRef<[u8]>>(columns: &[T]) -> Vec<u8> {
+        let mut record = b"replaced".to_vec();
+        format_tsv_into(columns.iter().map(AsRef::as_ref), &mut record);
+        record
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!("a\tb c\t".as_bytes(), format_tsv(&["a", "b c", ""]));
+    }
+
+    #[test]
+    fn escaped() {
+        assert_eq!(
+            "a\\tb\tc\\nd\\r\te\\\\f".as_bytes(),
+            format_tsv(&["a\tb", "c\nd\r", "e\\f"])
+        );
+    }
+}
+
+#[cfg(test)]
+mod format_json {
+    use super::*;
+
+    fn format_json_array<T: AsRef<[u8]>>(columns: &[T]) -> Vec<u8> {
+        let mut array = b"replaced".to_vec();
+        format_json_array_into(columns.iter().map(AsRef::as_ref), &mut array);
+        array
+    }
+
+    fn format_json_object<K: AsRef<[u8]>, T: AsRef<[u8]>>(keys: &[K], columns: &[T]) -> Vec<u8> {
+        let mut object = b"replaced".to_vec();
+        let keys = keys.iter().map(|key| JsonKey::Name(key.as_ref()));
+        format_json_object_into(keys.zip(columns.iter().map(AsRef::as_ref)), &mut object);
+        object
+    }
+
+    #[test]
+    fn array() {
+        assert_eq!(
+            "[\"a\",\"b c\",\"\"]".as_bytes(),
+            format_json_array(&["a", "b c", ""])
+        );
+        assert_eq!("[]".as_bytes(), format_json_array::<&str>(&[]));
+    }
+
+    #[test]
+    fn escaped() {
+        assert_eq!(
+            "[\"\\\"q\\\"\",\"\\\\\",\"\\n\\r\\t\",\"\\u0001\",\"é\"]".as_bytes(),
+            format_json_array(&["\"q\"", "\\", "\n\r\t", "\u{1}", "é"])
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            "[\"a\u{fffd}b\"]".as_bytes(),
+            format_json_array(&[b"a\xffb"])
+        );
+    }
+
+    #[test]
+    fn object() {
+        assert_eq!(
+            "{\"NAME\":\"web-1\",\"AGE\":\"5d\"}".as_bytes(),
+            format_json_object(&["NAME", "AGE"], &["web-1", "5d"])
+        );
+        assert_eq!("{}".as_bytes(), format_json_object::<&str, &str>(&[], &[]));
+    }
+
+    #[test]
+    fn number_keys() {
+        let mut object = vec![];
+        let keys_and_columns = [
+            (JsonKey::Name(b"NAME"), &b"web-1"[..]),
+            (JsonKey::Number(3), &b"x"[..]),
+        ];
+        format_json_object_into(keys_and_columns, &mut object);
+        assert_eq!("{\"NAME\":\"web-1\",\"3\":\"x\"}".as_bytes(), object);
+    }
+}
+
+#[cfg(test)]
+mod aligner {
+    use super::*;
+
+    fn align(
+        alignments: Vec<Alignment>,
+        buffer_lines: Option<usize>,
+        lines: &[&[&str]],
+    ) -> Vec<String> {
+        let mut output_strings = vec![];
+        let mut output_handler = |output: &[u8]| {
+            output_strings.push(String::from_utf8(output.to_vec()).unwrap());
+        };
+        let mut aligner = Aligner::new(alignments, b"  ", buffer_lines);
+        for line in lines {
+            let columns = line
+                .iter()
+                .map(|column| column.as_bytes().to_vec())
+                .collect();
+            aligner.push(columns, &mut output_handler);
+        }
+        aligner.flush(&mut output_handler);
+        output_strings
+    }
+
+    #[test]
+    fn left() {
+        let expected = vec!["a    bb  c", "aaa  b   cccc", "", "a"];
+        let lines: &[&[&str]] = &[&["a", "bb", "c"], &["aaa", "b", "cccc"], &[], &["a"]];
+        assert_eq!(expected, align(vec![Alignment::Left], None, lines));
+        assert_eq!(expected, align(vec![], None, lines));
+    }
+
+    #[test]
+    fn right_and_numeric() {
+        let expected = vec!["  a  x    1.5", "aaa  yyy  -10", "  b  zzz  n/a"];
+        let lines: &[&[&str]] = &[
+            &["a", "x", "1.5"],
+            &["aaa", "yyy", "-10"],
+            &["b", "zzz", "n/a"],
+        ];
+        assert_eq!(
+            expected,
+            align(vec![Alignment::Right, Alignment::Numeric], None, lines)
+        );
+    }
+
+    #[test]
+    fn display_width_is_used() {
+        let expected = vec!["日本  x", "abcd  y", "é     z"];
+        let lines: &[&[&str]] = &[&["日本", "x"], &["abcd", "y"], &["é", "z"]];
+        assert_eq!(expected, align(vec![Alignment::Left], None, lines));
+    }
+
+    #[test]
+    fn bounded_buffer() {
+        // The first two lines are aligned together, then the next two lines, which are wider, so
+        // the widths grow, then the last line, which uses the widths of the earlier lines.
+        let expected = vec!["a   x", "bb  y", "c     z", "dddd  w", "e     v"];
+        let lines: &[&[&str]] = &[
+            &["a", "x"],
+            &["bb", "y"],
+            &["c", "z"],
+            &["dddd", "w"],
+            &["e", "v"],
+        ];
+        assert_eq!(expected, align(vec![Alignment::Left], Some(2), lines));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut output = vec![];
+        let mut aligner = Aligner::new(vec![Alignment::Right], b" ", None);
+        aligner.push(vec![b"\xff".to_vec()], &mut |line: &[u8]| {
+            output.push(line.to_vec())
+        });
+        aligner.push(vec![b"abc".to_vec()], &mut |line: &[u8]| {
+            output.push(line.to_vec())
+        });
+        aligner.flush(&mut |line: &[u8]| output.push(line.to_vec()));
+        assert_eq!(vec![b"  \xff".to_vec(), b"abc".to_vec()], output);
+    }
+}
+
+#[cfg(test)]
+mod record_writer {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer whose output can be inspected while a RecordWriter owns it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A writer that always fails like a pipe whose reader has exited.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered() {
+        let output = SharedBuffer::default();
+        let mut writer = RecordWriter::new(output.clone(), b"\n".to_vec(), false);
+        writer.write_record(b"a b").unwrap();
+        writer.write_record(b"c").unwrap();
+        assert_eq!(b"".to_vec(), *output.0.borrow());
+        writer.flush().unwrap();
+        assert_eq!(b"a b\nc\n".to_vec(), *output.0.borrow());
+    }
+
+    #[test]
+    fn line_buffered() {
+        let output = SharedBuffer::default();
+        let mut writer = RecordWriter::new(output.clone(), b"\0".to_vec(), true);
+        writer.write_record(b"a b").unwrap();
+        assert_eq!(b"a b\0".to_vec(), *output.0.borrow());
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let mut writer = RecordWriter::new(BrokenPipe, b"\n".to_vec(), false);
+        writer.write_record(b"a").unwrap();
+        assert_eq!(
+            std::io::ErrorKind::BrokenPipe,
+            writer.flush().unwrap_err().kind()
+        );
+        let mut writer = RecordWriter::new(BrokenPipe, b"\n".to_vec(), true);
+        assert!(writer.write_record(b"a").is_err());
+    }
+}
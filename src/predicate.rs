@@ -0,0 +1,619 @@
+//! Row filtering with --where predicates.
+//!
+//! A predicate compares a column with a value, e.g. `3>100`, `1==root`, or `-1~'^/bin/'`, and
+//! predicates can be combined with `!`, `&&`, `||`, and parentheses.  `&&` binds more tightly than
+//! `||`.  Columns are column numbers, the virtual columns F, N, and NR, or column names with
+//! --header; like column specifiers, a header column named like a virtual column takes precedence
+//! over the virtual column.  Values are either bare words, or quoted with single or double quotes,
+//! which is necessary for values that contain whitespace or any of `()!=<>~&|`.
+//!
+//! Operators:
+//! - `==` and `!=` compare strings.
+//! - `~` and `!~` match a regex.
+//! - `<`, `<=`, `>`, and `>=` compare numbers; columns that are not numbers never match.
+//!
+//! Comparisons with columns that do not exist in a line never match.
+
+use super::ColumnBound;
+use super::Columns;
+use super::VirtualColumn;
+use super::resolve_column_bound;
+use regex::bytes::Regex;
+
+/// Comparison operators.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Match,
+    NotMatch,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// The value that a column is compared with.
+#[derive(Debug)]
+pub enum Value {
+    String(Vec<u8>),
+    Regex(Regex),
+    Number(f64),
+}
+
+/// A parsed predicate.
+#[derive(Debug)]
+pub enum Predicate {
+    Comparison {
+        column: ColumnBound,
+        operator: Operator,
+        value: Value,
+    },
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Operator(Operator),
+    Word(String),
+    Quoted(String),
+}
+
+impl Operator {
+    /// The operator as it's written in a predicate.
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Match => "~",
+            Operator::NotMatch => "!~",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+/// Formats a token as it's written in a predicate, for error messages.
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::LeftParen => f.write_str("("),
+            Token::RightParen => f.write_str(")"),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Not => f.write_str("!"),
+            Token::Operator(operator) => f.write_str(operator.symbol()),
+            Token::Word(word) => f.write_str(word),
+            Token::Quoted(value) => write!(f, "'{value}'"),
+        }
+    }
+}
+
+/// Characters that end a bare word.
+const SPECIAL_CHARACTERS: &str = "()!=<>~&|'\"";
+
+/// Splits a predicate into tokens.
+fn tokenize(predicate: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = predicate.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, length) = match c {
+            '(' => (Token::LeftParen, 1),
+            ')' => (Token::RightParen, 1),
+            '~' => (Token::Operator(Operator::Match), 1),
+            '&' | '|' => {
+                let token = if c == '&' { Token::And } else { Token::Or };
+                if !rest[1..].starts_with(c) {
+                    return Err(format!("expected {c}{c} but found a single {c}"));
+                }
+                (token, 2)
+            }
+            '!' | '=' | '<' | '>' => {
+                let equals = rest[1..].starts_with('=');
+                match (c, equals) {
+                    ('!', true) => (Token::Operator(Operator::NotEqual), 2),
+                    ('!', false) if rest[1..].starts_with('~') => {
+                        (Token::Operator(Operator::NotMatch), 2)
+                    }
+                    ('!', false) => (Token::Not, 1),
+                    ('=', true) => (Token::Operator(Operator::Equal), 2),
+                    ('=', false) => return Err(String::from("expected == but found a single =")),
+                    ('<', true) => (Token::Operator(Operator::LessOrEqual), 2),
+                    ('<', false) => (Token::Operator(Operator::Less), 1),
+                    ('>', true) => (Token::Operator(Operator::GreaterOrEqual), 2),
+                    _ => (Token::Operator(Operator::Greater), 1),
+                }
+            }
+            '\'' | '"' => match rest[1..].find(c) {
+                Some(end) => (Token::Quoted(String::from(&rest[1..=end])), end + 2),
+                None => return Err(format!("unterminated quoted value: {rest}")),
+            },
+            _ => {
+                let length = rest
+                    .find(|c: char| c.is_whitespace() || SPECIAL_CHARACTERS.contains(c))
+                    .unwrap_or(rest.len());
+                (Token::Word(String::from(&rest[..length])), length)
+            }
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser for predicates.
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    allow_names: bool,
+}
+
+impl Parser {
+    // predicate := and ( "||" and )*
+    fn parse_or(&mut self) -> Result<Predicate, String> {
+        let mut predicate = self.parse_and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            predicate = Predicate::Or(Box::new(predicate), Box::new(self.parse_and()?));
+        }
+        Ok(predicate)
+    }
+
+    // and := unary ( "&&" unary )*
+    fn parse_and(&mut self) -> Result<Predicate, String> {
+        let mut predicate = self.parse_unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            predicate = Predicate::And(Box::new(predicate), Box::new(self.parse_unary()?));
+        }
+        Ok(predicate)
+    }
+
+    // unary := "!" unary | "(" predicate ")" | comparison
+    fn parse_unary(&mut self) -> Result<Predicate, String> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(Predicate::Not(Box::new(self.parse_unary()?))),
+            Some(Token::LeftParen) => {
+                let predicate = self.parse_or()?;
+                match self.tokens.next() {
+                    Some(Token::RightParen) => Ok(predicate),
+                    _ => Err(String::from("expected )")),
+                }
+            }
+            Some(Token::Word(column)) => self.parse_comparison(column),
+            Some(token) => Err(format!("expected a column but found {token}")),
+            None => Err(String::from("expected a column but found the end")),
+        }
+    }
+
+    // comparison := column operator value
+    fn parse_comparison(&mut self, column: String) -> Result<Predicate, String> {
+        let column = match column.parse::<isize>() {
+            Ok(number) => ColumnBound::Number(number),
+            Err(_) if column.bytes().all(|b| b == b'-' || b.is_ascii_digit()) => {
+                return Err(format!("invalid column number: {column}"));
+            }
+            Err(_) if !self.allow_names && VirtualColumn::from_name(&column).is_none() => {
+                return Err(format!("column names like {column} require --header"));
+            }
+            Err(_) => ColumnBound::Name(column),
+        };
+        let operator = match self.tokens.next() {
+            Some(Token::Operator(operator)) => operator,
+            _ => {
+                return Err(String::from(
+                    "expected a comparison operator after the column",
+                ));
+            }
+        };
+        let value = match self.tokens.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+            _ => {
+                return Err(String::from(
+                    "expected a value after the comparison operator",
+                ));
+            }
+        };
+        let value = match operator {
+            Operator::Equal | Operator::NotEqual => Value::String(value.into_bytes()),
+            Operator::Match | Operator::NotMatch => match Regex::new(&value) {
+                Ok(regex) => Value::Regex(regex),
+                Err(error_message) => return Err(format!("{error_message}")),
+            },
+            _ => match value.parse::<f64>() {
+                Ok(number) => Value::Number(number),
+                Err(_) => return Err(format!("expected a number but found {value}")),
+            },
+        };
+        Ok(Predicate::Comparison {
+            column,
+            operator,
+            value,
+        })
+    }
+}
+
+impl Predicate {
+    /// Parses a predicate.  Column names are only accepted if allow_names is true.
+    pub fn parse(predicate: &str, allow_names: bool) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(predicate)?.into_iter().peekable(),
+            allow_names,
+        };
+        let parsed = parser.parse_or()?;
+        match parser.tokens.next() {
+            None => Ok(parsed),
+            Some(token) => Err(format!("unexpected {token}")),
+        }
+    }
+
+    /// Returns whether any comparison might use a virtual column, in which case matches() needs
+    /// their values.
+    pub fn uses_virtual_columns(&self) -> bool {
+        match self {
+            Predicate::Comparison { column, .. } => virtual_column(column).is_some(),
+            Predicate::Not(predicate) => predicate.uses_virtual_columns(),
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                left.uses_virtual_columns() || right.uses_virtual_columns()
+            }
+        }
+    }
+
+    /// Replaces column names with column numbers by looking them up in the header columns.
+    /// Virtual column names that aren't header column names are left for matches().
+    pub fn resolve_column_names<T: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        header: &[&T],
+    ) -> Result<(), String> {
+        match self {
+            Predicate::Comparison { column, .. } => {
+                let in_header = |name: &str| {
+                    header
+                        .iter()
+                        .skip(1)
+                        .any(|column| column.as_ref() == name.as_bytes())
+                };
+                if let ColumnBound::Name(name) = column
+                    && VirtualColumn::from_name(name).is_some()
+                    && !in_header(name)
+                {
+                    return Ok(());
+                }
+                *column = ColumnBound::Number(resolve_column_bound(column, header)?);
+                Ok(())
+            }
+            Predicate::Not(predicate) => predicate.resolve_column_names(header),
+            Predicate::And(left, right) | Predicate::Or(left, right) => {
+                left.resolve_column_names(header)?;
+                right.resolve_column_names(header)
+            }
+        }
+    }
+
+    /// Returns whether the columns of a line match this predicate.  virtual_columns holds the values of the virtual columns in the order of VirtualColumn::ALL,
+    /// and may be empty if uses_virtual_columns() is false.  Column names must have been resolved
+    /// with resolve_column_names().
+    pub fn matches(&self, columns: Columns, virtual_columns: &[&[u8]]) -> bool {
+        match self {
+            Predicate::Comparison {
+                column,
+                operator,
+                value,
+            } => {
+                let column = match column {
+                    ColumnBound::Number(number) => {
+                        super::resolve_index(*number, columns.len()).map(|k| columns.get(k))
+                    }
+                    ColumnBound::Name(_) => virtual_column(column)
+                        .and_then(|v| virtual_columns.get(v))
+                        .copied(),
+                };
+                match column {
+                    Some(column) => compare(column, *operator, value),
+                    None => false,
+                }
+            }
+            Predicate::Not(predicate) => !predicate.matches(columns, virtual_columns),
+            Predicate::And(left, right) => {
+                left.matches(columns, virtual_columns) && right.matches(columns, virtual_columns)
+            }
+            Predicate::Or(left, right) => {
+                left.matches(columns, virtual_columns) || right.matches(columns, virtual_columns)
+            }
+        }
+    }
+}
+
+/// Returns the position in VirtualColumn::ALL of the virtual column named by column, if any.
+fn virtual_column(column: &ColumnBound) -> Option<usize> {
+    let ColumnBound::Name(name) = column else {
+        return None;
+    };
+    let column = VirtualColumn::from_name(name)?;
+    VirtualColumn::ALL.iter().position(|&other| other == column)
+}
+
+/// Compares a column with a value.
+fn compare(column: &[u8], operator: Operator, value: &Value) -> bool {
+    match (operator, value) {
+        (Operator::Equal, Value::String(string)) => column == string.as_slice(),
+        (Operator::NotEqual, Value::String(string)) => column != string.as_slice(),
+        (Operator::Match, Value::Regex(regex)) => regex.is_match(column),
+        (Operator::NotMatch, Value::Regex(regex)) => !regex.is_match(column),
+        (_, Value::Number(number)) => {
+            let Some(column) = std::str::from_utf8(column)
+                .ok()
+                .and_then(|column| column.trim().parse::<f64>().ok())
+            else {
+                return false;
+            };
+            match operator {
+                Operator::Less => column < *number,
+                Operator::LessOrEqual => column <= *number,
+                Operator::Greater => column > *number,
+                _ => column >= *number,
+            }
+        }
+        // parse_comparison() never creates any other combinations.
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tokenize {
+    use super::*;
+
+    #[test]
+    fn all_tokens() {
+        let expected = vec![
+            Token::Not,
+            Token::LeftParen,
+            Token::Word(String::from("-1")),
+            Token::Operator(Operator::NotEqual),
+            Token::Quoted(String::from("a b")),
+            Token::RightParen,
+            Token::And,
+            Token::Word(String::from("NAME")),
+            Token::Operator(Operator::Match),
+            Token::Word(String::from("^web$")),
+            Token::Or,
+            Token::Word(String::from("2")),
+            Token::Operator(Operator::NotMatch),
+            Token::Quoted(String::from("x|y")),
+            Token::Or,
+            Token::Word(String::from("3")),
+            Token::Operator(Operator::Equal),
+            Token::Word(String::from("c")),
+        ];
+        assert_eq!(
+            Ok(expected),
+            tokenize(" !(-1 != 'a b') && NAME~^web$ || 2!~\"x|y\"||3==c ")
+        );
+    }
+
+    #[test]
+    fn numeric_operators() {
+        let expected = vec![
+            Token::Operator(Operator::Less),
+            Token::Operator(Operator::LessOrEqual),
+            Token::Operator(Operator::Greater),
+            Token::Operator(Operator::GreaterOrEqual),
+        ];
+        assert_eq!(Ok(expected), tokenize("< <= > >="));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(String::from("expected && but found a single &")),
+            tokenize("1==a & 2==b")
+        );
+        assert_eq!(
+            Err(String::from("expected || but found a single |")),
+            tokenize("1==a | 2==b")
+        );
+        assert_eq!(
+            Err(String::from("expected == but found a single =")),
+            tokenize("1=a")
+        );
+        assert_eq!(
+            Err(String::from("unterminated quoted value: 'a")),
+            tokenize("1=='a")
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse {
+    use super::*;
+
+    fn parse_error(predicate: &str, allow_names: bool) -> String {
+        Predicate::parse(predicate, allow_names).unwrap_err()
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "column names like NAME require --header",
+            parse_error("NAME==a", false)
+        );
+        assert_eq!("invalid column number: 1-", parse_error("1-==a", false));
+        assert_eq!(
+            "expected a comparison operator after the column",
+            parse_error("1 a", false)
+        );
+        assert_eq!(
+            "expected a value after the comparison operator",
+            parse_error("1==", false)
+        );
+        assert_eq!("expected a number but found a", parse_error("1>a", false));
+        assert!(parse_error("1~[a", false).contains("regex parse error"));
+        assert_eq!("expected )", parse_error("(1==a", false));
+        assert_eq!("expected a column but found )", parse_error(")", false));
+        assert_eq!(
+            "expected a column but found &&",
+            parse_error("&& 1==a", false)
+        );
+        assert_eq!(
+            "expected a column but found the end",
+            parse_error("1==a &&", false)
+        );
+        assert_eq!("unexpected )", parse_error("1==a)", false));
+        assert_eq!("unexpected b", parse_error("1==a b", false));
+        assert_eq!("unexpected 'b c'", parse_error("1==a 'b c'", false));
+        assert_eq!("unexpected >=", parse_error("1==a >=", false));
+        assert_eq!("expected && but found a single &", parse_error("&", false));
+    }
+
+    #[test]
+    fn names_allowed() {
+        assert!(Predicate::parse("NAME==a", true).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod matches {
+    use super::*;
+    use crate::text_and_spans;
+
+    fn matches(predicate: &str, line: &str) -> bool {
+        let mut columns: Vec<&[u8]> = vec![line.as_bytes()];
+        columns.extend(line.split_whitespace().map(str::as_bytes));
+        let predicate = Predicate::parse(predicate, false).unwrap();
+        matches_columns(&predicate, &columns, &[])
+    }
+
+    pub(super) fn matches_columns(
+        predicate: &Predicate,
+        columns: &[&[u8]],
+        virtual_columns: &[&[u8]],
+    ) -> bool {
+        let (text, spans) = text_and_spans(columns);
+        predicate.matches(Columns::new(&text, &spans), virtual_columns)
+    }
+
+    #[test]
+    fn strings() {
+        assert!(matches("2==b", "a b c"));
+        assert!(!matches("2==a", "a b c"));
+        assert!(matches("2!=a", "a b c"));
+        assert!(!matches("2!=b", "a b c"));
+        assert!(matches("0=='a b c'", "a b c"));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches("-1~'^c+$'", "a b ccc"));
+        assert!(!matches("1~^b", "a b ccc"));
+        assert!(matches("1!~^b", "a b ccc"));
+        assert!(!matches("1!~^a", "a b ccc"));
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(matches("2>100", "a 101"));
+        assert!(!matches("2>100", "a 100"));
+        assert!(matches("2>=100", "a 100"));
+        assert!(matches("2<1e3", "a 999.5"));
+        assert!(!matches("2<-1", "a -1"));
+        assert!(matches("2<=-1", "a -1"));
+        assert!(!matches("2>1", "a many"));
+        assert!(!matches("2<1", "a many"));
+    }
+
+    #[test]
+    fn invalid_utf8_is_not_a_number() {
+        let columns: [&[u8]; 2] = [b"\xff", b"\xff"];
+        let predicate = Predicate::parse("1<1", false).unwrap();
+        assert!(!matches_columns(&predicate, &columns, &[]));
+    }
+
+    #[test]
+    fn missing_columns_never_match() {
+        assert!(!matches("5==a", "a b"));
+        assert!(!matches("5!=a", "a b"));
+        assert!(matches("!5==a", "a b"));
+    }
+
+    #[test]
+    fn combinations() {
+        assert!(matches("1==a && 2==b", "a b"));
+        assert!(!matches("1==a && 2==c", "a b"));
+        assert!(matches("1==x || 2==b", "a b"));
+        assert!(!matches("1==x || 2==c", "a b"));
+        // && binds more tightly than ||.
+        assert!(matches("1==a || 1==x && 2==x", "a b"));
+        assert!(!matches("(1==a || 1==x) && 2==x", "a b"));
+        assert!(matches("!(1==x) && !!2==b", "a b"));
+    }
+
+    #[test]
+    fn virtual_columns() {
+        let predicate = Predicate::parse("N>1 && F==a.log", false).unwrap();
+        assert!(predicate.uses_virtual_columns());
+        let virtual_columns: [&[u8]; 3] = [b"a.log", b"2", b"7"];
+        assert!(matches_columns(&predicate, &[b"x"], &virtual_columns));
+        let virtual_columns: [&[u8]; 3] = [b"a.log", b"1", b"7"];
+        assert!(!matches_columns(&predicate, &[b"x"], &virtual_columns));
+        assert!(
+            !Predicate::parse("1==a", false)
+                .unwrap()
+                .uses_virtual_columns()
+        );
+    }
+
+    #[test]
+    fn unresolved_names_never_match() {
+        let predicate = Predicate::parse("NAME==a", true).unwrap();
+        assert!(!matches_columns(&predicate, &[b"a", b"a"], &[]));
+    }
+}
+
+#[cfg(test)]
+mod resolve_column_names {
+    use super::matches::matches_columns;
+    use super::*;
+
+    #[test]
+    fn resolved() {
+        let mut predicate = Predicate::parse("!(A==1 && B==2) || C==3", true).unwrap();
+        predicate
+            .resolve_column_names(&["A B C", "A", "B", "C"])
+            .unwrap();
+        assert!(matches_columns(&predicate, &[b"", b"1", b"3", b"9"], &[]));
+        assert!(!matches_columns(&predicate, &[b"", b"1", b"2", b"9"], &[]));
+        assert!(matches_columns(&predicate, &[b"", b"1", b"2", b"3"], &[]));
+    }
+
+    #[test]
+    fn header_names_take_precedence_over_virtual_columns() {
+        let mut predicate = Predicate::parse("F==1 && NR==2", true).unwrap();
+        predicate.resolve_column_names(&["F S", "F", "S"]).unwrap();
+        let virtual_columns: [&[u8]; 3] = [b"-", b"2", b"2"];
+        assert!(matches_columns(
+            &predicate,
+            &[b"", b"1", b"S"],
+            &virtual_columns
+        ));
+        assert!(!matches_columns(
+            &predicate,
+            &[b"", b"-", b"S"],
+            &virtual_columns
+        ));
+    }
+
+    #[test]
+    fn missing_name() {
+        let mut predicate = Predicate::parse("A==1 && D==2", true).unwrap();
+        assert_eq!(
+            Err(String::from("Column name \"D\" not found in header.")),
+            predicate.resolve_column_names(&["A B C", "A", "B", "C"])
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! Splitting input into records, for -z/--null-data and --record-separator.
+//!
+//! Like awk's RS, a separator that is a single character is used literally, an empty separator
+//! selects paragraph mode where records are separated by blank lines, and anything else is a regex.
+//!
+//! Records are read with [ReadRecord] into a buffer provided by the caller, so that reading a record
+//! doesn't allocate once the buffer is large enough.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::io::Result;
+use std::ops::Range;
+
+use regex::bytes::Regex;
+
+/// What separates input records.
+pub enum RecordSeparator {
+    Byte(u8),
+    Regex(Regex),
+    /// Records are separated by one or more blank lines, and leading and trailing newlines in the
+    /// input are ignored.  The regex matches the first blank line of a separator, which is then
+    /// extended over any further newlines.
+    Paragraph(Regex),
+}
+
+impl RecordSeparator {
+    /// Parses a separator given with --record-separator.
+    pub fn parse(separator: &str) -> std::result::Result<Self, regex::Error> {
+        let mut chars = separator.chars();
+        match (chars.next(), chars.next()) {
+            (None, _) => Ok(RecordSeparator::Paragraph(Regex::new(r"\n\n").unwrap())),
+            (Some(_), None) if separator.len() == 1 => {
+                Ok(RecordSeparator::Byte(separator.as_bytes()[0]))
+            }
+            (Some(_), None) => Regex::new(&regex::escape(separator)).map(RecordSeparator::Regex),
+            _ => Regex::new(separator).map(RecordSeparator::Regex),
+        }
+    }
+
+    /// Returns a reader of the records read from reader, without their separators.  A separator at
+    /// the end of the input doesn't produce an empty record.  When records are separated by
+    /// newlines "\r\n" line endings are stripped too, like [BufRead::lines].  With lossy, invalid
+    /// UTF-8 in each record is replaced with U+FFFD REPLACEMENT CHARACTER.
+    pub fn records<R: BufRead>(&self, reader: R, lossy: bool) -> Records<'_, R> {
+        let max_separator_len = match self {
+            RecordSeparator::Byte(_) => Some(1),
+            RecordSeparator::Regex(regex) | RecordSeparator::Paragraph(regex) => {
+                max_match_len(regex)
+            }
+        };
+        Records {
+            reader,
+            separator: self,
+            lossy,
+            strip_carriage_returns: true,
+            buffer: vec![],
+            eof: false,
+            search_start: 0,
+            max_separator_len,
+        }
+    }
+}
+
+/// Returns the length in bytes of the longest string that regex can match, or None if that isn't
+/// bounded, e.g. for --+.
+fn max_match_len(regex: &Regex) -> Option<usize> {
+    regex_syntax::ParserBuilder::new()
+        .utf8(false)
+        .build()
+        .parse(regex.as_str())
+        .ok()?
+        .properties()
+        .maximum_len()
+}
+
+/// Reads records into a buffer provided by the caller.
+pub trait ReadRecord {
+    /// Replaces the contents of record with the next record, returning false rather than changing
+    /// record at the end of the input.
+    fn read_record(&mut self, record: &mut Vec<u8>) -> Result<bool>;
+}
+
+impl<R: ReadRecord + ?Sized> ReadRecord for Box<R> {
+    fn read_record(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        (**self).read_record(record)
+    }
+}
+
+/// The records read by [RecordSeparator::records].  Records can also be read with [Iterator], which
+/// allocates a new Vec for every record.
+pub struct Records<'s, R> {
+    reader: R,
+    separator: &'s RecordSeparator,
+    lossy: bool,
+    strip_carriage_returns: bool,
+    // Data that has been read but not yet returned.
+    buffer: Vec<u8>,
+    eof: bool,
+    // Where in buffer to start searching for the next separator, so that data isn't searched again
+    // every time more is read.
+    search_start: usize,
+    max_separator_len: Option<usize>,
+}
+
+impl<R: BufRead> Records<'_, R> {
+    /// Keeps the "\r" of "\r\n" line endings, e.g. so that CSV fields containing CRLF line breaks
+    /// are kept intact.
+    pub fn keep_carriage_returns(mut self) -> Self {
+        self.strip_carriage_returns = false;
+        self
+    }
+
+    /// Reads the next record for a byte separator.
+    fn read_delimited(&mut self, byte: u8, record: &mut Vec<u8>) -> Result<bool> {
+        record.clear();
+        if self.reader.read_until(byte, record)? == 0 {
+            return Ok(false);
+        }
+        if record.last() == Some(&byte) {
+            record.pop();
+        }
+        if byte == b'\n' && self.strip_carriage_returns && record.last() == Some(&b'\r') {
+            record.pop();
+        }
+        Ok(true)
+    }
+
+    /// Reads the next record for a regex separator, reading until the buffer contains a separator
+    /// or the input ends.  A separator that reaches the end of the buffer isn't used until more
+    /// data has been read, because it might continue, e.g. --+.
+    fn read_matched(
+        &mut self,
+        regex: &Regex,
+        paragraph: bool,
+        record: &mut Vec<u8>,
+    ) -> Result<bool> {
+        loop {
+            if paragraph {
+                let leading = self.buffer.iter().take_while(|&&b| b == b'\n').count();
+                self.buffer.drain(..leading);
+                self.search_start = self.search_start.saturating_sub(leading);
+            }
+            if let Some(separator) = self.find_separator(regex, paragraph) {
+                record.clear();
+                record.extend_from_slice(&self.buffer[..separator.start]);
+                self.buffer.drain(..separator.end);
+                self.search_start = 0;
+                return Ok(true);
+            }
+            if self.eof {
+                if paragraph {
+                    while self.buffer.last() == Some(&b'\n') {
+                        self.buffer.pop();
+                    }
+                }
+                if self.buffer.is_empty() {
+                    return Ok(false);
+                }
+                record.clear();
+                record.append(&mut self.buffer);
+                self.search_start = 0;
+                return Ok(true);
+            }
+            match self.reader.fill_buf() {
+                Ok([]) => self.eof = true,
+                Ok(data) => {
+                    let length = data.len();
+                    self.buffer.extend_from_slice(data);
+                    self.reader.consume(length);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the position of the first non-empty separator in the buffer that can be used.  When
+    /// there isn't one, remembers where the search must resume once more data has been read: at
+    /// the start of a separator that reached the end of the buffer, or far enough back from the
+    /// end that a separator ending in the new data is found.  When separators are unbounded, e.g.
+    /// a+b, the search has to start again at the beginning of the buffer.
+    fn find_separator(&mut self, regex: &Regex, paragraph: bool) -> Option<Range<usize>> {
+        let mut start = self.search_start;
+        let found = loop {
+            if start > self.buffer.len() {
+                break None;
+            }
+            match regex.find_at(&self.buffer, start) {
+                Some(m) if m.is_empty() => start = m.end() + 1,
+                found => break found.map(|m| m.range()),
+            }
+        };
+        // A paragraph separator continues over any further newlines.
+        let found = found.map(|separator| {
+            let newlines = match paragraph {
+                true => self.buffer[separator.end..]
+                    .iter()
+                    .take_while(|&&b| b == b'\n')
+                    .count(),
+                false => 0,
+            };
+            separator.start..separator.end + newlines
+        });
+        match found {
+            Some(separator) if self.eof || separator.end < self.buffer.len() => Some(separator),
+            found => {
+                let overlap_start = self.max_separator_len.map_or(0, |max| {
+                    self.buffer.len().saturating_sub(max.saturating_sub(1))
+                });
+                self.search_start = found.map_or(overlap_start, |separator| {
+                    separator.start.min(overlap_start)
+                });
+                None
+            }
+        }
+    }
+}
+
+impl<R: BufRead> ReadRecord for Records<'_, R> {
+    fn read_record(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        let found = match self.separator {
+            RecordSeparator::Byte(byte) => self.read_delimited(*byte, record)?,
+            RecordSeparator::Regex(regex) => self.read_matched(regex, false, record)?,
+            RecordSeparator::Paragraph(regex) => self.read_matched(regex, true, record)?,
+        };
+        // Valid UTF-8 is by far the most common case, and from_utf8_lossy() doesn't allocate for it.
+        if found
+            && self.lossy
+            && let std::borrow::Cow::Owned(replaced) = String::from_utf8_lossy(record)
+        {
+            *record = replaced.into_bytes();
+        }
+        Ok(found)
+    }
+}
+
+impl<R: BufRead> Iterator for Records<'_, R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = vec![];
+        match self.read_record(&mut record) {
+            Ok(true) => Some(Ok(record)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Adapts an iterator of records, e.g. [crate::csv::CsvRecords], to [ReadRecord].  Records are
+/// moved into the caller's buffer rather than copied.
+pub struct IterRecords<I>(pub I);
+
+impl<I: Iterator<Item = Result<Vec<u8>>>> ReadRecord for IterRecords<I> {
+    fn read_record(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        match self.0.next() {
+            Some(next) => {
+                *record = next?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Reads some records ahead so that they can be inspected, e.g. for --aligned, then returns them
+/// before reading the rest of the records from inner.
+pub struct ReadAhead<R> {
+    buffered: VecDeque<Result<Vec<u8>>>,
+    inner: R,
+}
+
+impl<R: ReadRecord> ReadAhead<R> {
+    /// Reads up to count records from inner, stopping early at the end of the input or after an
+    /// error.  The error is returned when reading reaches it.
+    pub fn new(mut inner: R, count: usize) -> Self {
+        let mut buffered = VecDeque::new();
+        while buffered.len() < count {
+            let mut record = vec![];
+            match inner.read_record(&mut record) {
+                Ok(true) => buffered.push_back(Ok(record)),
+                Ok(false) => break,
+                Err(e) => {
+                    buffered.push_back(Err(e));
+                    break;
+                }
+            }
+        }
+        Self { buffered, inner }
+    }
+
+    /// Returns the records that were read ahead, up to the first error.
+    pub fn records(&self) -> Vec<&[u8]> {
+        self.buffered
+            .iter()
+            .map_while(|record| record.as_deref().ok())
+            .collect()
+    }
+}
+
+impl<R: ReadRecord> ReadRecord for ReadAhead<R> {
+    fn read_record(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        match self.buffered.pop_front() {
+            Some(buffered) => {
+                *record = buffered?;
+                Ok(true)
+            }
+            None => self.inner.read_record(record),
+        }
+    }
+}
+
+#[cfg(test)]
+mod record_separator {
+    use super::*;
+
+    fn records(separator: &str, input: &str) -> Vec<String> {
+        let separator = RecordSeparator::parse(separator).unwrap();
+        separator
+            .records(input.as_bytes(), false)
+            .map(|record| String::from_utf8(record.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn byte() {
+        assert_eq!(vec!["a", "b c", ""], records("\0", "a\0b c\0\0"));
+        assert_eq!(vec!["a", "b"], records("\0", "a\0b"));
+        assert_eq!(Vec::<String>::new(), records("\0", ""));
+    }
+
+    #[test]
+    fn single_character_is_literal() {
+        assert_eq!(vec!["a", "b"], records("|", "a|b|"));
+        assert_eq!(vec!["a", "b"], records("§", "a§b"));
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(vec!["a", "b", "c"], records(";+|,", "a;;b,c"));
+        assert_eq!(vec!["a", "b\n"], records("--+", "a---b\n"));
+    }
+
+    #[test]
+    fn separator_spanning_reads() {
+        // A BufReader with a tiny buffer returns one byte at a time.
+        let separator = RecordSeparator::parse("--+").unwrap();
+        let reader = std::io::BufReader::with_capacity(1, "a----b".as_bytes());
+        let records: Vec<Vec<u8>> = separator
+            .records(reader, false)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], records);
+    }
+
+    #[test]
+    fn search_resumes_after_reads() {
+        for (separator, input, expected) in [
+            ("abc", "xxabcyyabc", vec!["xx", "yy"]),
+            ("a(bc|b)d", "xabdyabcdz", vec!["x", "y", "z"]),
+            ("-+;", "a---;b", vec!["a", "b"]),
+            ("", "a\nb\n\n\n\nc\n", vec!["a\nb", "c"]),
+        ] {
+            let separator = RecordSeparator::parse(separator).unwrap();
+            let reader = std::io::BufReader::with_capacity(1, input.as_bytes());
+            let records: Vec<String> = separator
+                .records(reader, false)
+                .map(|record| String::from_utf8(record.unwrap()).unwrap())
+                .collect();
+            assert_eq!(expected, records, "input {input:?}");
+        }
+    }
+
+    #[test]
+    fn empty_matches_are_ignored() {
+        assert_eq!(vec!["a", "b"], records("x*", "axxb"));
+    }
+
+    #[test]
+    fn paragraph() {
+        assert_eq!(
+            vec!["a\nb", "c", "d\ne"],
+            records("", "\n\na\nb\n\nc\n\n\n\nd\ne\n\n")
+        );
+        assert_eq!(vec!["a"], records("", "a\n"));
+        assert_eq!(Vec::<String>::new(), records("", "\n\n"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(RecordSeparator::parse("(a").is_err());
+    }
+
+    #[test]
+    fn carriage_returns() {
+        assert_eq!(vec!["a", "b\rc", "d"], records("\n", "a\r\nb\rc\nd\r\n"));
+        assert_eq!(vec!["a\r", "b"], records(";", "a\r;b"));
+        let separator = RecordSeparator::parse("\n").unwrap();
+        let records: Vec<Vec<u8>> = separator
+            .records(&b"a\r\nb\n"[..], false)
+            .keep_carriage_returns()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec![b"a\r".to_vec(), b"b".to_vec()], records);
+    }
+
+    #[test]
+    fn lossy() {
+        let separator = RecordSeparator::parse("\n").unwrap();
+        let records: Vec<Vec<u8>> = separator
+            .records(&b"a\xffb\nc\n"[..], true)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            vec!["a\u{FFFD}b".as_bytes().to_vec(), b"c".to_vec()],
+            records
+        );
+    }
+
+    #[test]
+    fn buffer_is_reused() {
+        let separator = RecordSeparator::parse("\n").unwrap();
+        let mut records = separator.records(&b"abc\nd\n"[..], false);
+        let mut record = vec![];
+        assert!(records.read_record(&mut record).unwrap());
+        assert_eq!(b"abc", record.as_slice());
+        let capacity = record.capacity();
+        assert!(records.read_record(&mut record).unwrap());
+        assert_eq!(b"d", record.as_slice());
+        assert_eq!(capacity, record.capacity());
+        assert!(!records.read_record(&mut record).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod read_ahead {
+    use super::*;
+
+    fn read_all(reader: &mut dyn ReadRecord) -> Vec<Result<Vec<u8>>> {
+        let mut records = vec![];
+        let mut record = vec![];
+        loop {
+            match reader.read_record(&mut record) {
+                Ok(true) => records.push(Ok(record.clone())),
+                Ok(false) => return records,
+                Err(e) => {
+                    records.push(Err(e));
+                    return records;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn records_are_returned_in_order() {
+        let separator = RecordSeparator::parse("\n").unwrap();
+        let mut read_ahead = ReadAhead::new(separator.records(&b"a\nb\nc\n"[..], false), 2);
+        let expected: Vec<&[u8]> = vec![b"a", b"b"];
+        assert_eq!(expected, read_ahead.records());
+        let records: Vec<Vec<u8>> = read_all(&mut read_ahead)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], records);
+    }
+
+    #[test]
+    fn short_input() {
+        let separator = RecordSeparator::parse("\n").unwrap();
+        let read_ahead = ReadAhead::new(separator.records(&b"a\n"[..], false), 5);
+        let expected: Vec<&[u8]> = vec![b"a"];
+        assert_eq!(expected, read_ahead.records());
+    }
+
+    #[test]
+    fn errors() {
+        let lines = vec![Ok(b"a".to_vec()), Err(std::io::Error::other("oh no!"))];
+        let mut read_ahead = ReadAhead::new(IterRecords(lines.into_iter()), 5);
+        let expected: Vec<&[u8]> = vec![b"a"];
+        assert_eq!(expected, read_ahead.records());
+        let records = read_all(&mut read_ahead);
+        assert_eq!(2, records.len());
+        assert!(records[1].is_err());
+    }
+}
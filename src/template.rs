@@ -0,0 +1,242 @@
+//! Output templates for --format, e.g. `{1}@{3}:{-1}`.
+//!
+//! Each placeholder in braces contains a column specifier, which is replaced with the matching
+//! columns joined with --separator.  `{{` and `}}` are literal braces, and `\n`, `\t`, and `\\` are
+//! a newline, a tab, and a backslash.
+
+use super::ColumnRange;
+use super::Columns;
+use super::IndexBuffers;
+use super::extract_indices_into;
+use super::push_columns;
+
+/// Part of a template: either literal text or a placeholder.  Placeholders hold the index of their
+/// column range.
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(Vec<u8>),
+    Placeholder(usize),
+}
+
+/// A parsed template.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parses a template, returning the template and the column specifiers from its placeholders.
+    /// The column specifiers must be parsed by the caller, and the resulting column ranges passed
+    /// to render_into() in the same order.
+    pub fn parse(template: &str) -> Result<(Self, Vec<String>), String> {
+        let mut parts = vec![];
+        let mut placeholders = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => {
+                    return Err(String::from(
+                        "unmatched } in template; use }} for a literal }",
+                    ));
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(String::from("unterminated placeholder in template"));
+                    };
+                    if end == 0 {
+                        return Err(String::from("empty placeholder in template"));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal).into_bytes()));
+                    }
+                    parts.push(Part::Placeholder(placeholders.len()));
+                    placeholders.push(String::from(&rest[..end]));
+                    chars = rest[end + 1..].chars();
+                }
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => return Err(format!("unknown escape \\{other} in template")),
+                    None => return Err(String::from("template ends with \\")),
+                },
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal.into_bytes()));
+        }
+        Ok((Self { parts }, placeholders))
+    }
+
+    /// Renders the template for a line into output, replacing its contents.  column_ranges[i] is
+    /// the column range for the i'th placeholder.  virtual_columns holds the values of the virtual
+    /// columns, as for extract_columns.  Columns from each placeholder are joined with separator.
+    /// buffers and output are reused for every line, so rendering doesn't allocate.
+    pub fn render_into(
+        &self,
+        column_ranges: &[ColumnRange],
+        columns: Columns,
+        virtual_columns: &[&[u8]],
+        separator: &[u8],
+        buffers: &mut IndexBuffers,
+        output: &mut Vec<u8>,
+    ) {
+        output.clear();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => output.extend_from_slice(literal),
+                Part::Placeholder(i) => {
+                    extract_indices_into(&column_ranges[*i..=*i], columns.len(), buffers);
+                    push_columns(
+                        &buffers.results,
+                        columns,
+                        virtual_columns,
+                        separator,
+                        output,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod parse {
+    use super::*;
+
+    #[test]
+    fn placeholders_and_literals() {
+        let (template, placeholders) = Template::parse("{1}@{3}:{-1}").unwrap();
+        assert_eq!(
+            Template {
+                parts: vec![
+                    Part::Placeholder(0),
+                    Part::Literal(b"@".to_vec()),
+                    Part::Placeholder(1),
+                    Part::Literal(b":".to_vec()),
+                    Part::Placeholder(2),
+                ],
+            },
+            template
+        );
+        assert_eq!(vec!["1", "3", "-1"], placeholders);
+    }
+
+    #[test]
+    fn escapes() {
+        let (template, placeholders) = Template::parse("{{x}}\\t{NAME}\\n\\\\é").unwrap();
+        assert_eq!(
+            Template {
+                parts: vec![
+                    Part::Literal(b"{x}\t".to_vec()),
+                    Part::Placeholder(0),
+                    Part::Literal("\n\\é".as_bytes().to_vec()),
+                ],
+            },
+            template
+        );
+        assert_eq!(vec!["NAME"], placeholders);
+    }
+
+    #[test]
+    fn empty_template() {
+        let (template, placeholders) = Template::parse("").unwrap();
+        assert_eq!(Template { parts: vec![] }, template);
+        assert_eq!(Vec::<String>::new(), placeholders);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(String::from(
+                "unmatched } in template; use }} for a literal }"
+            )),
+            Template::parse("a}b")
+        );
+        assert_eq!(
+            Err(String::from("unterminated placeholder in template")),
+            Template::parse("a{1")
+        );
+        assert_eq!(
+            Err(String::from("empty placeholder in template")),
+            Template::parse("a{}")
+        );
+        assert_eq!(
+            Err(String::from("unknown escape \\x in template")),
+            Template::parse("\\x")
+        );
+        assert_eq!(
+            Err(String::from("template ends with \\")),
+            Template::parse("a\\")
+        );
+    }
+}
+
+#[cfg(test)]
+mod render {
+    use super::*;
+    use crate::VirtualColumn;
+    use crate::text_and_spans;
+
+    fn render_line(
+        template: &Template,
+        column_ranges: &[ColumnRange],
+        columns: &[&[u8]],
+        virtual_columns: &[&[u8]],
+        separator: &[u8],
+    ) -> Vec<u8> {
+        let (text, spans) = text_and_spans(columns);
+        let mut output = b"replaced".to_vec();
+        template.render_into(
+            column_ranges,
+            Columns::new(&text, &spans),
+            virtual_columns,
+            separator,
+            &mut IndexBuffers::default(),
+            &mut output,
+        );
+        output
+    }
+
+    #[test]
+    fn render() {
+        let (template, _) = Template::parse("{1}={2:3}!{9}\\n").unwrap();
+        let column_ranges = [
+            ColumnRange::new(1, 1),
+            ColumnRange::new(2, 3),
+            ColumnRange::new(9, 9),
+        ];
+        let columns: [&[u8]; 4] = [b"a b c", b"a", b"b", b"c"];
+        assert_eq!(
+            b"a=b, c!\n".to_vec(),
+            render_line(&template, &column_ranges, &columns, &[], b", ")
+        );
+    }
+
+    #[test]
+    fn render_virtual_columns() {
+        let (template, _) = Template::parse("{F}:{N}: {1}").unwrap();
+        let column_ranges = [
+            ColumnRange::virtual_column(VirtualColumn::Filename, false),
+            ColumnRange::virtual_column(VirtualColumn::LineNumber, false),
+            ColumnRange::new(1, 1),
+        ];
+        let columns: [&[u8]; 2] = [b"a", b"a"];
+        let virtual_columns: [&[u8]; 3] = [b"file", b"7", b"9"];
+        assert_eq!(
+            b"file:7: a".to_vec(),
+            render_line(&template, &column_ranges, &columns, &virtual_columns, b" ")
+        );
+    }
+}
@@ -44,6 +44,37 @@ fn test_missing_columns() {
     ));
 }
 
+#[test]
+fn test_malformed_column() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("1::2").arg("testdata/file1");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid column specifier \"1::2\": expected a column number at character 3",
+    ));
+}
+
+#[test]
+fn test_malformed_column_names_existing_file() {
+    let dir = std::env::temp_dir().join(format!("colx-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1::2"), "a b\n").unwrap();
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.current_dir(&dir).arg("2").arg("1::2");
+    cmd.assert().success().stdout("b\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_columns_flag_makes_every_argument_a_filename() {
+    let dir = std::env::temp_dir().join(format!("colx-test-columns-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("2"), "a b c\n").unwrap();
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.current_dir(&dir).arg("-c").arg("-1,1").arg("2");
+    cmd.assert().success().stdout("c a\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
 #[test]
 fn test_invalid_delimiter() {
     let mut cmd = Command::cargo_bin("colx").unwrap();
@@ -94,3 +125,311 @@ fn test_reversed_range() {
     cmd.arg("2:1").arg("testdata/file1");
     cmd.assert().success().stdout("is This\n\nis It\n");
 }
+
+#[test]
+fn test_header_names() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--header").arg("AGE").arg("NAME");
+    cmd.write_stdin("NAME AGE\nbob 42\n");
+    cmd.assert().success().stdout("AGE NAME\n42 bob\n");
+}
+
+#[test]
+fn test_header_name_shadows_virtual_column() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--header").arg("F").arg("PID").arg("N");
+    cmd.write_stdin("F S PID\n1 S 42\n");
+    cmd.assert().success().stdout("F PID N\n1 42 2\n");
+}
+
+#[test]
+fn test_where_virtual_columns() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--where").arg("N>1 && F==-").arg("1");
+    cmd.write_stdin("a\nb\nc\n");
+    cmd.assert().success().stdout("b\nc\n");
+}
+
+#[test]
+fn test_header_ambiguous_name() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--header")
+        .arg("NAME")
+        .arg("testdata/file_with_duplicate_header");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("\"NAME\" is ambiguous"));
+}
+
+#[test]
+fn test_csv() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--csv").arg("2").arg("1");
+    cmd.write_stdin("a,\"b,c\"\n\"multi\nline\",d\n");
+    cmd.assert().success().stdout("b,c a\nd multi\nline\n");
+}
+
+#[test]
+fn test_csv_crlf_line_breaks() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--csv")
+        .arg("--output-format")
+        .arg("csv")
+        .arg("2")
+        .arg("0");
+    cmd.write_stdin("a,\"b\r\nc\",d\r\n");
+    cmd.assert()
+        .success()
+        .stdout("\"b\r\nc\",\"a,\"\"b\r\nc\"\",d\"\n");
+}
+
+#[test]
+fn test_output_format_json() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--output-format")
+        .arg("json")
+        .arg("--header")
+        .arg("B");
+    cmd.write_stdin("A B\n1 \"two\"\n");
+    cmd.assert().success().stdout("{\"B\":\"\\\"two\\\"\"}\n");
+}
+
+#[test]
+fn test_invalid_utf8() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("2").write_stdin(b"\xff\xfe caf\xe9\n".to_vec());
+    cmd.assert().success().stdout(b"caf\xe9\n".to_vec());
+}
+
+#[test]
+fn test_open_ended_range() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--").arg("-2:").write_stdin("a b c d\ne\n");
+    cmd.assert().success().stdout("c d\ne\n");
+}
+
+#[test]
+fn test_exclude_last_column() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("^-1").write_stdin("a b c\nd e\nf\n");
+    cmd.assert().success().stdout("a b\nd\n\n");
+}
+
+#[test]
+fn test_complement() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--complement").arg("2").write_stdin("a b c\n");
+    cmd.assert().success().stdout("a c\n");
+}
+
+#[test]
+fn test_keep_empty() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--keep-empty").arg("-d").arg(":").arg("3");
+    cmd.write_stdin("a::c\n");
+    cmd.assert().success().stdout("c\n");
+}
+
+#[test]
+fn test_where() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--where").arg("3>100 || 1~^b").arg("1");
+    cmd.write_stdin("a x 50\nb y 5\nc z 500\n");
+    cmd.assert().success().stdout("b\nc\n");
+}
+
+#[test]
+fn test_align() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--align=r").arg("1:");
+    cmd.write_stdin("a bbb\ncc d\n");
+    cmd.assert().success().stdout(" a bbb\ncc   d\n");
+}
+
+#[test]
+fn test_format() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--format").arg("mv {1} {2}.bak").arg("-");
+    cmd.write_stdin("a b\n");
+    cmd.assert().success().stdout("mv a b.bak\n");
+}
+
+#[test]
+fn test_compressed_files() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("1")
+        .arg("testdata/file1.gz")
+        .arg("testdata/file3")
+        .arg("testdata/file1.zst");
+    cmd.assert()
+        .success()
+        .stdout("This\n\nIt\nFile\ndeleted\nThis\n\nIt\n");
+}
+
+#[test]
+fn test_compressed_stdin() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("2");
+    cmd.write_stdin(std::fs::read("testdata/file1.xz").unwrap());
+    cmd.assert().success().stdout("is\n\nis\n");
+}
+
+#[test]
+fn test_missing_file_continues() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("1")
+        .arg("testdata/does_not_exist")
+        .arg("testdata/file3");
+    cmd.assert()
+        .code(2)
+        .stdout("File\ndeleted\n")
+        .stderr("colx: testdata/does_not_exist: No such file or directory\n");
+}
+
+#[test]
+fn test_missing_file_strict() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--strict")
+        .arg("1")
+        .arg("testdata/file1")
+        .arg("testdata/does_not_exist")
+        .arg("testdata/file3");
+    cmd.assert()
+        .code(1)
+        .stdout("")
+        .stderr("colx: testdata/does_not_exist: No such file or directory\n");
+}
+
+#[test]
+fn test_read_error_includes_line_number() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("1").arg("testdata");
+    cmd.assert()
+        .code(2)
+        .stderr("colx: testdata:1: Is a directory\n");
+}
+
+#[test]
+fn test_virtual_columns() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("F")
+        .arg("N")
+        .arg("NR")
+        .arg("1")
+        .arg("-")
+        .arg("testdata/file3");
+    cmd.write_stdin("a b\n");
+    cmd.assert()
+        .success()
+        .stdout("- 1 1 a\ntestdata/file3 1 2 File\ntestdata/file3 2 3 deleted\n");
+}
+
+#[test]
+fn test_fixed_delimiter() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("-F").arg("-d").arg("|").arg("2");
+    cmd.write_stdin("a|b|c\n");
+    cmd.assert().success().stdout("b\n");
+}
+
+#[test]
+fn test_pattern() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--pattern")
+        .arg(r"user=(?<user>\w+) action=(\w+)")
+        .arg("2")
+        .arg("user")
+        .arg("testdata/file_with_log_lines");
+    cmd.assert().success().stdout("login bob\nlogout alice\n");
+}
+
+#[test]
+fn test_fixed_widths() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--widths").arg("3,3").arg("--trim").arg("2");
+    cmd.write_stdin("a  b c d\n");
+    cmd.assert().success().stdout("b c\n");
+}
+
+#[test]
+fn test_aligned() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--aligned")
+        .arg("--aligned-lines")
+        .arg("5")
+        .arg("1")
+        .arg("3");
+    cmd.write_stdin("  PID TTY   CMD\n12345 pts/0 sleep 10\n");
+    cmd.assert().success().stdout("PID CMD\n12345 sleep 10\n");
+}
+
+#[test]
+fn test_null_data() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("-z").arg("1");
+    cmd.write_stdin("a b\0c d\0");
+    cmd.assert().success().stdout("a\0c\0");
+}
+
+#[test]
+fn test_record_separators() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--record-separator")
+        .arg(";")
+        .arg("--output-record-separator")
+        .arg(",")
+        .arg("2");
+    cmd.write_stdin("a b;c d;");
+    cmd.assert().success().stdout("b,d,");
+}
+
+#[test]
+fn test_preserve_delimiters() {
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--preserve-delimiters").arg("2:3");
+    cmd.write_stdin("a  b\t c    d\n");
+    cmd.assert().success().stdout("b\t c\n");
+
+    let mut cmd = Command::cargo_bin("colx").unwrap();
+    cmd.arg("--preserve-delimiters").arg("2:3").arg("4");
+    cmd.write_stdin("a  b\t c    d\n");
+    cmd.assert().success().stdout("b\t c d\n");
+}
+
+#[test]
+fn test_broken_pipe_exits_quietly() {
+    use std::io::Read;
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("colx"))
+        .arg("1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // Write much more than a pipe can hold, ignoring the error when colx exits.
+    let writer = std::thread::spawn(move || {
+        let line = b"column1 column2\n".repeat(1024);
+        for _ in 0..1024 {
+            if stdin.write_all(&line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut first_bytes = [0; 8];
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_exact(&mut first_bytes)
+        .unwrap();
+    // Dropping stdout closed the pipe, so colx's next write fails.
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    assert_eq!(b"column1\n", &first_bytes);
+    assert_eq!(Some(141), output.status.code());
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+}